@@ -24,7 +24,14 @@ impl TextureAnimation {
         }
     }
 
-    pub fn draw(&self, x: f32, y: f32, flip_x: bool, animation_controller: &AnimationController) {
+    pub fn draw(
+        &self,
+        x: f32,
+        y: f32,
+        flip_x: bool,
+        color: Color,
+        animation_controller: &AnimationController,
+    ) {
         let animation = &animation_controller.animations[animation_controller.current_animation];
         let texture_x = animation_controller.frame * self.width;
         let texture_y = animation.row * self.height;
@@ -55,7 +62,7 @@ impl TextureAnimation {
             self.texture,
             draw_x * UPSCALE,
             (y + self.offset.y) * UPSCALE,
-            WHITE,
+            color,
             params,
         );
     }
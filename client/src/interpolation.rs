@@ -0,0 +1,196 @@
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::{PI, TAU};
+
+use shared::{math::lerp, TickRate, Transform};
+use shipyard::*;
+
+use crate::ClientState;
+
+const MAX_SNAPSHOTS: usize = 64;
+// Snapshots are at least a tick apart, a longer delay would render before the oldest one.
+const MAX_DELAY: f32 = (MAX_SNAPSHOTS - 1) as f32;
+// When the render tick drifts further than this from the target it is reset.
+const MAX_TICK_DRIFT: f32 = 30.;
+
+#[derive(Debug)]
+pub struct InterpolationConfig {
+    // How many server ticks the remote entities are rendered behind the last received frame.
+    delay: f32,
+    // How many ticks an entity can be extrapolated after its last snapshot.
+    max_extrapolation: f32,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self {
+            delay: 6.,
+            max_extrapolation: 4.,
+        }
+    }
+}
+
+impl InterpolationConfig {
+    pub fn delay(&self) -> f32 {
+        self.delay
+    }
+
+    /// The delay is clamped to the ticks kept in the snapshot buffer.
+    pub fn set_delay(&mut self, delay: f32) {
+        self.delay = delay.clamp(0., MAX_DELAY);
+    }
+
+    pub fn max_extrapolation(&self) -> f32 {
+        self.max_extrapolation
+    }
+
+    pub fn set_max_extrapolation(&mut self, max_extrapolation: f32) {
+        self.max_extrapolation = max_extrapolation.clamp(0., MAX_DELAY);
+    }
+}
+
+struct Snapshot {
+    tick: u64,
+    transforms: HashMap<EntityId, Transform>,
+}
+
+#[derive(Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+    render_tick: Option<f32>,
+}
+
+impl SnapshotBuffer {
+    /// Server tick the remote entities are currently rendered at.
+    pub fn render_tick(&self) -> Option<f32> {
+        self.render_tick
+    }
+
+    fn latest_tick(&self) -> Option<u64> {
+        self.snapshots.back().map(|s| s.tick)
+    }
+
+    fn insert(&mut self, snapshot: Snapshot) {
+        if self.snapshots.len() >= MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        let position = self
+            .snapshots
+            .iter()
+            .rposition(|s| s.tick < snapshot.tick)
+            .map_or(0, |p| p + 1);
+        self.snapshots.insert(position, snapshot);
+    }
+
+    fn sample(&self, entity_id: EntityId, tick: f32, max_extrapolation: f32) -> Option<Transform> {
+        let mut before: Option<(u64, &Transform)> = None;
+        let mut previous: Option<(u64, &Transform)> = None;
+
+        for snapshot in self.snapshots.iter() {
+            let transform = match snapshot.transforms.get(&entity_id) {
+                Some(transform) => transform,
+                None => continue,
+            };
+
+            if snapshot.tick as f32 > tick {
+                return match before {
+                    // Interpolate between the surrounding snapshots
+                    Some((before_tick, before)) => {
+                        let t = (tick - before_tick as f32) / (snapshot.tick - before_tick) as f32;
+                        Some(interpolate(before, transform, t))
+                    }
+                    // The entity is newer than the render time
+                    None => Some(transform.clone()),
+                };
+            }
+
+            previous = before;
+            before = Some((snapshot.tick, transform));
+        }
+
+        // No snapshot after the render time, extrapolate from the last two
+        let (last_tick, last) = before?;
+        match previous {
+            Some((previous_tick, previous)) => {
+                let ticks = (tick - last_tick as f32).min(max_extrapolation);
+                let t = 1. + ticks / (last_tick - previous_tick) as f32;
+                Some(interpolate(previous, last, t))
+            }
+            None => Some(last.clone()),
+        }
+    }
+}
+
+fn interpolate(from: &Transform, to: &Transform, t: f32) -> Transform {
+    Transform::new(
+        lerp(from.position..=to.position, t),
+        lerp_angle(from.rotation, to.rotation, t),
+    )
+}
+
+fn lerp_angle(from: f32, to: f32, t: f32) -> f32 {
+    let mut difference = (to - from) % TAU;
+    if difference > PI {
+        difference -= TAU;
+    } else if difference < -PI {
+        difference += TAU;
+    }
+    from + difference * t
+}
+
+/// Stores the transforms of the frame that was just applied to the world.
+pub fn record_snapshot(
+    tick: u64,
+    transforms: View<Transform>,
+    mut snapshot_buffer: UniqueViewMut<SnapshotBuffer>,
+) {
+    let transforms = transforms
+        .iter()
+        .with_id()
+        .map(|(entity_id, transform)| (entity_id, transform.clone()))
+        .collect();
+
+    snapshot_buffer.insert(Snapshot { tick, transforms });
+}
+
+/// Renders the remote entities a fixed delay behind the server,
+/// the local player is skipped since it is predicted.
+pub fn interpolate_remote_entities(
+    frame_time: f32,
+    config: UniqueView<InterpolationConfig>,
+    tick_rate: UniqueView<TickRate>,
+    client_state: UniqueView<ClientState>,
+    mut snapshot_buffer: UniqueViewMut<SnapshotBuffer>,
+    mut transforms: ViewMut<Transform>,
+) {
+    let latest_tick = match snapshot_buffer.latest_tick() {
+        Some(tick) => tick,
+        None => return,
+    };
+
+    let target_tick = latest_tick as f32 - config.delay;
+    let render_tick = match snapshot_buffer.render_tick {
+        Some(render_tick) => {
+            let render_tick = render_tick + frame_time / tick_rate.frame_time();
+            if (target_tick - render_tick).abs() > MAX_TICK_DRIFT {
+                target_tick
+            } else {
+                // Slowly converge to the target so packet jitter does not cause jumps
+                render_tick + (target_tick - render_tick) * 0.1
+            }
+        }
+        None => target_tick,
+    };
+    snapshot_buffer.render_tick = Some(render_tick);
+
+    for (entity_id, mut transform) in (&mut transforms).iter().with_id() {
+        if client_state.entity_id == Some(entity_id) {
+            continue;
+        }
+
+        if let Some(sampled) =
+            snapshot_buffer.sample(entity_id, render_tick, config.max_extrapolation)
+        {
+            *transform = sampled;
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use ldtk_rust::{Project, TileInstance};
 use macroquad::prelude::*;
-use shared::ldtk::{load_project, BASE_DIR};
+use shared::ldtk::{load_project, BASE_DIR, DEFAULT_LEVEL};
 use shipyard::{UniqueView, UniqueViewMut, World};
 use std::collections::HashMap;
 
@@ -74,6 +74,16 @@ impl TextureAtlas {
     }
 }
 
+// Identifier of the level played in the server.
+#[derive(Debug)]
+pub struct CurrentLevel(pub String);
+
+impl Default for CurrentLevel {
+    fn default() -> Self {
+        Self(DEFAULT_LEVEL.to_string())
+    }
+}
+
 #[derive(Debug)]
 pub struct SpriteSheets(HashMap<i64, TextureAtlas>);
 
@@ -117,9 +127,19 @@ pub fn draw_level(
     project: UniqueView<Project>,
     sprite_sheets: UniqueView<SpriteSheets>,
     textures: UniqueView<Textures>,
+    current_level: UniqueView<CurrentLevel>,
 ) {
+    let level = match project
+        .levels
+        .iter()
+        .find(|level| level.identifier == current_level.0)
+    {
+        Some(level) => level,
+        None => return,
+    };
+
     // Draw background
-    if let Some(bg_path) = project.levels[0].bg_rel_path.as_ref() {
+    if let Some(bg_path) = level.bg_rel_path.as_ref() {
         if let Some(bg_texture) = textures.0.get(bg_path) {
             let dest_size = vec2(bg_texture.width(), bg_texture.height());
             let dest_size = Some(dest_size * UPSCALE);
@@ -132,7 +152,7 @@ pub fn draw_level(
         }
     }
 
-    for (_, layer) in project.levels[0]
+    for (_, layer) in level
         .layer_instances
         .as_ref()
         .unwrap()
@@ -1,35 +1,59 @@
 use macroquad::prelude::*;
 use shared::{
-    channels_config,
-    message::{ClientAction, ServerMessages},
-    network::ServerFrame,
+    discovery::LanBrowser,
+    ldtk::{
+        load_level_collisions, replace_level_collisions, LevelHill, LevelNotFound, LevelRotation,
+        DEFAULT_LEVEL,
+    },
+    message::{
+        decode_message, ClientAction, FrameAck, Handshake, HandshakeResponse, JoinRequest, Scene,
+        ServerInfo, ServerMessages, Standing,
+    },
+    network::{register_components, FrameHistory, NetworkRegistry, ServerFrame},
     physics::render_physics,
-    player::Player,
+    player::{GameplayConfig, Player, PlayerInput},
     projectile::Projectile,
-    Channel, EntityMapping, LobbyInfo, PlayersScore, Transform,
+    transport::{
+        memory_transport, ClientTransport, ConditionedTransport, ConditionerHandle,
+        MultiServerTransport, UdpClientTransport, UdpServerTransport,
+    },
+    Channel, EntityMapping, GameModeKind, LobbyInfo, MatchRules, PlayerId, PlayersScore,
+    PlayersStats, SessionToken, Team, TickRate, Transform,
 };
 
-use renet_udp::{client::UdpClient, renet::remote_connection::ConnectionConfig};
-
 use alto_logger::TermLogger;
 use shipyard::*;
-use ui::{draw_connect_menu, draw_lobby, draw_score, ConnectMenuResponse, UiState};
+use ui::{
+    draw_connect_menu, draw_hill, draw_leave_button, draw_level_vote, draw_lobby,
+    draw_match_results, draw_match_rules, draw_mode_vote, draw_network_menu, draw_pause_menu,
+    draw_reconnecting, draw_scoreboard, draw_spectating, draw_team_pick, draw_team_score,
+    ConnectMenuResponse, PauseMenuResponse, UiState,
+};
 
-use std::net::{SocketAddr, UdpSocket};
+use std::net::SocketAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, time::Instant};
 
-use level::{draw_level, load_project_and_assets};
+use level::{draw_level, load_project_and_assets, CurrentLevel};
 
 mod animation;
+mod interpolation;
 mod level;
 mod player;
+mod recent_servers;
 mod ui;
 
 use crate::animation::{AnimationTextures, Textures};
-use crate::player::{draw_players, load_player_texture, player_input, track_client_entity};
+use crate::interpolation::{
+    interpolate_remote_entities, record_snapshot, InterpolationConfig, SnapshotBuffer,
+};
+use crate::player::{
+    draw_players, load_player_texture, player_input, predict_local_player, reconcile_local_player,
+    track_client_entity, InputPrediction,
+};
+use crate::recent_servers::RecentServers;
 
-use server::Game;
+use server::{Game, ServerConfig};
 
 #[macroquad::main("Renet macroquad demo")]
 async fn main() {
@@ -66,10 +90,19 @@ pub const RX: f32 = 336.;
 pub const RY: f32 = 192.;
 pub const UPSCALE: f32 = 10.;
 
+// When the client falls behind more than this, the remaining time is dropped.
+const MAX_TICKS_PER_FRAME: u32 = 5;
+
+// Attempts to reconnect after losing the connection, and the seconds between them.
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_DELAY: f32 = 2.;
+
 pub enum Screen {
     Connect,
     Lobby,
     Gameplay,
+    // Standings of the match that just ended, the server is back in the lobby.
+    Results,
 }
 
 struct App {
@@ -78,15 +111,48 @@ struct App {
     world: World,
     camera: Camera2D,
     render_target: RenderTarget,
-    client: Option<UdpClient>,
+    client: Option<Box<dyn ClientTransport>>,
     lobby_info: LobbyInfo,
     ui: UiState,
     server: Option<Game>,
     last_updated: Instant,
+    network_registry: NetworkRegistry,
+    frame_history: FrameHistory,
+    last_frame: Option<u64>,
+    // Time not yet simulated by the local player prediction.
+    tick_accumulator: f32,
+    pending_dash: bool,
+    // Simulated conditions of the connection to the server, changed in the network menu.
+    network_conditions: ConditionerHandle,
+    // Address of the remote server, none when hosting.
+    server_addr: Option<SocketAddr>,
+    // Given by the server to resume the player after reconnecting.
+    session_token: Option<SessionToken>,
+    // Whether the server accepted the handshake of the current connection.
+    accepted: bool,
+    // Whether the join request was sent in the current connection.
+    joined: bool,
+    reconnect: Option<Reconnect>,
+    // Levels of the server and how it picks the next one.
+    levels: Vec<String>,
+    level_rotation: LevelRotation,
+    rules: MatchRules,
+    standings: Vec<Standing>,
+    team_scores: Vec<(Team, u8)>,
+    // Looks for games in the local network while in the connect menu.
+    lan_browser: Option<LanBrowser>,
+    recent_servers: RecentServers,
+}
+
+// Connection lost, retrying with the session token until the attempts run out.
+struct Reconnect {
+    attempts: u32,
+    delay: f32,
 }
 
 pub struct ClientState {
-    pub client_id: SocketAddr,
+    // Assigned by the server when connected.
+    pub player_id: Option<PlayerId>,
     pub entity_id: Option<EntityId>,
 }
 
@@ -102,10 +168,12 @@ impl App {
             ..Default::default()
         };
 
-        let world = World::new();
+        let mut world = World::new();
+        // Level collisions are used for the local player prediction
+        load_level_collisions(&mut world, DEFAULT_LEVEL).expect("Default level not found.");
 
         let client_info = ClientState {
-            client_id: id,
+            player_id: None,
             entity_id: None,
         };
 
@@ -116,12 +184,29 @@ impl App {
         let mapping: EntityMapping = HashMap::new();
         world.add_unique(mapping).unwrap();
         world.add_unique(PlayersScore::default()).unwrap();
+        world.add_unique(PlayersStats::default()).unwrap();
+        world.add_unique(GameplayConfig::default()).unwrap();
+        world.add_unique(TickRate::default()).unwrap();
+        world.add_unique(CurrentLevel::default()).unwrap();
+        world.add_unique(InputPrediction::default()).unwrap();
+        world.add_unique(InterpolationConfig::default()).unwrap();
+        world.add_unique(SnapshotBuffer::default()).unwrap();
 
         // Tracking of components
         world.borrow::<ViewMut<Player>>().unwrap().track_all();
 
+        let network_registry = register_components();
+
+        let lan_browser = match LanBrowser::new() {
+            Ok(lan_browser) => Some(lan_browser),
+            Err(e) => {
+                println!("LAN discovery disabled: {}", e);
+                None
+            }
+        };
+
         let server = None;
-        let client: Option<UdpClient> = None;
+        let client: Option<Box<dyn ClientTransport>> = None;
         let screen = Screen::Connect;
 
         Self {
@@ -135,6 +220,24 @@ impl App {
             client,
             server,
             last_updated: Instant::now(),
+            network_registry,
+            frame_history: FrameHistory::default(),
+            last_frame: None,
+            tick_accumulator: 0.,
+            pending_dash: false,
+            network_conditions: ConditionerHandle::default(),
+            server_addr: None,
+            session_token: None,
+            accepted: false,
+            joined: false,
+            reconnect: None,
+            levels: vec![],
+            level_rotation: LevelRotation::Fixed,
+            rules: MatchRules::default(),
+            standings: vec![],
+            team_scores: vec![],
+            lan_browser,
+            recent_servers: RecentServers::load(),
         }
     }
 
@@ -149,78 +252,133 @@ impl App {
         let now = Instant::now();
         let frame_duration = now - self.last_updated;
         self.last_updated = now;
-        let mut has_client_error = false;
+        let mut client_error = None;
+        let mut messages = vec![];
         if let Some(client) = self.client.as_mut() {
             if let Err(e) = client.update(frame_duration) {
-                self.ui.connect_error = Some(format!("{}", e));
-                self.screen = Screen::Connect;
-                self.server = None;
-                has_client_error = true;
                 println!("Client update error: {}", e);
+                client_error = Some(e);
             } else {
                 while let Some(message) = client.receive_message(Channel::Reliable.id()) {
-                    let server_message: ServerMessages = bincode::deserialize(&message).unwrap();
-                    match server_message {
-                        ServerMessages::UpdateScore(score) => {
-                            let mut player_scores =
-                                self.world.borrow::<UniqueViewMut<PlayersScore>>().unwrap();
-                            player_scores.score = score.score;
-                        }
-                        ServerMessages::UpdateLobby(lobby_info) => {
-                            self.lobby_info = lobby_info;
-                        }
-                        ServerMessages::StartGameplay => {
-                            self.screen = Screen::Gameplay;
-                        }
-                    }
+                    messages.push(message);
                 }
             }
         }
-        if has_client_error {
+        if let Some(e) = client_error {
             self.client = None;
+            self.connection_lost(e.to_string());
+        }
+        self.update_reconnect(frame_duration.as_secs_f32());
+        for message in messages {
+            if self.client.is_none() {
+                break;
+            }
+            if !self.accepted {
+                self.handle_handshake_response(&message);
+                continue;
+            }
+            match decode_message::<ServerMessages>(&message) {
+                Ok(server_message) => self.handle_server_message(server_message),
+                Err(e) => println!("Error decoding server message: {}", e),
+            }
         }
 
         match self.screen {
             Screen::Gameplay => {
                 self.render_gameplayer();
+                if is_key_pressed(KeyCode::Escape) {
+                    self.ui.show_pause_menu = !self.ui.show_pause_menu;
+                }
+                if self.ui.show_pause_menu {
+                    self.draw_level_vote(32.);
+                    let PauseMenuResponse { resume, leave } = draw_pause_menu();
+                    if resume {
+                        self.ui.show_pause_menu = false;
+                    }
+                    if leave {
+                        self.leave();
+                    }
+                }
             }
             Screen::Connect => {
+                let lan_servers = match self.lan_browser.as_mut() {
+                    Some(lan_browser) => {
+                        lan_browser.update();
+                        lan_browser.servers()
+                    }
+                    None => vec![],
+                };
                 let ConnectMenuResponse {
                     addr,
                     host,
                     connect,
-                } = draw_connect_menu(&mut self.ui);
+                } = draw_connect_menu(&mut self.ui, &lan_servers, self.recent_servers.servers());
                 if let Some(server_addr) = addr {
                     if host {
                         self.host(server_addr);
                     } else if connect {
-                        self.ui.connect_error = None;
-                        self.screen = Screen::Lobby;
-                        let socket = UdpSocket::bind(self.id).unwrap();
-                        let connection_config = ConnectionConfig {
-                            channels_config: channels_config(),
-                            ..Default::default()
-                        };
-                        self.id = socket.local_addr().unwrap();
-                        let client =
-                            UdpClient::new(socket, server_addr, connection_config).unwrap();
-                        self.client = Some(client);
+                        self.session_token = None;
+                        match self.connect(server_addr) {
+                            Ok(()) => {
+                                self.ui.connect_error = None;
+                                self.screen = Screen::Lobby;
+                            }
+                            Err(e) => self.ui.connect_error = Some(e.to_string()),
+                        }
                     }
                 }
             }
             Screen::Lobby => {
-                if let Some(connection) = self.client.as_mut() {
-                    if draw_lobby(&self.lobby_info, self.id) {
-                        let message = bincode::serialize(&ClientAction::LobbyReady).unwrap();
-                        if let Err(e) = connection.send_message(Channel::Reliable.id(), message) {
-                            println!("error sending message: {}", e);
-                        }
+                if self.client.is_some() {
+                    let player_id = self
+                        .world
+                        .borrow::<UniqueView<ClientState>>()
+                        .unwrap()
+                        .player_id;
+                    if draw_lobby(&self.lobby_info, player_id) {
+                        self.send_action(&ClientAction::LobbyReady);
+                    }
+                    if let Some(team_pick) = draw_team_pick(&self.lobby_info, player_id) {
+                        self.send_action(&ClientAction::PickTeam(team_pick));
+                    }
+                    self.draw_level_vote(80.);
+                    if let Some(mode) = draw_mode_vote(&self.lobby_info, player_id, 114.) {
+                        self.send_action(&ClientAction::VoteMode(mode));
                     }
-                } else {
+                    draw_match_rules(self.lobby_info.mode, &self.rules);
+                    if draw_leave_button() {
+                        self.leave();
+                    }
+                } else if self.reconnect.is_none() {
                     self.screen = Screen::Connect;
                     self.lobby_info = LobbyInfo::default();
                 }
             }
+            Screen::Results => {
+                let player_id = self
+                    .world
+                    .borrow::<UniqueView<ClientState>>()
+                    .unwrap()
+                    .player_id;
+                if draw_match_results(&self.standings, &self.team_scores, player_id) {
+                    self.screen = Screen::Lobby;
+                }
+            }
+        }
+
+        if let Some(reconnect) = self.reconnect.as_ref() {
+            draw_reconnecting(reconnect.attempts, RECONNECT_ATTEMPTS);
+        }
+
+        if is_key_pressed(KeyCode::F1) {
+            self.ui.show_network_menu = !self.ui.show_network_menu;
+        }
+        if self.ui.show_network_menu {
+            let mut interpolation = self
+                .world
+                .borrow::<UniqueViewMut<InterpolationConfig>>()
+                .unwrap();
+            draw_network_menu(&self.network_conditions, &mut interpolation);
         }
 
         // Send messages to server
@@ -261,49 +419,449 @@ impl App {
     }
 
     fn host(&mut self, server_addr: SocketAddr) {
-        let s = Game::new(server_addr).unwrap();
-        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
-        self.id = socket.local_addr().unwrap();
-
-        let connection_config = ConnectionConfig {
-            channels_config: channels_config(),
-            ..Default::default()
+        let config = ServerConfig::default();
+        let udp = match UdpServerTransport::new(server_addr, config.max_clients) {
+            Ok(udp) => udp,
+            Err(e) => {
+                self.ui.connect_error = Some(format!("failed to host on {}: {}", server_addr, e));
+                return;
+            }
         };
-        let client_udp = UdpClient::new(socket, server_addr, connection_config).unwrap();
-        self.client = Some(client_udp);
+        // The hosting client talks to its own server in memory,
+        // remote clients still connect over UDP.
+        let (memory, connector) = memory_transport();
+        let transport = MultiServerTransport::new(vec![Box::new(udp), Box::new(memory)]);
+        let mut s = match Game::new(Box::new(transport), config) {
+            Ok(s) => s,
+            Err(e) => {
+                self.ui.connect_error = Some(format!("failed to load the levels: {}", e));
+                return;
+            }
+        };
+        s.enable_discovery(server_addr.port());
+
+        let client = connector.connect();
+        self.id = client.id();
+        let client = ConditionedTransport::new(client, self.network_conditions.clone());
+        self.client = Some(Box::new(client));
+        self.ui.connect_error = None;
         self.screen = Screen::Lobby;
         self.server = Some(s);
+        self.server_addr = None;
+        self.session_token = None;
+        self.joined = false;
+        self.accepted = false;
+        self.send_handshake();
+        self.reset_frames();
     }
 
-    fn render_gameplayer(&mut self) {
-        if self.client.is_none() {
+    fn connect(&mut self, server_addr: SocketAddr) -> Result<(), std::io::Error> {
+        // A new port each time, the server may still see the old connection
+        let client_addr = SocketAddr::new(self.id.ip(), 0);
+        let client = UdpClientTransport::new(client_addr, server_addr)?;
+        self.id = client.id();
+        let client = ConditionedTransport::new(client, self.network_conditions.clone());
+        self.client = Some(Box::new(client));
+        self.server_addr = Some(server_addr);
+        self.joined = false;
+        self.accepted = false;
+        self.send_handshake();
+        self.reset_frames();
+        Ok(())
+    }
+
+    // Tries to reconnect to a remote server, the player is kept by the server
+    // for a while. Otherwise goes back to the connect menu.
+    fn connection_lost(&mut self, error: String) {
+        let can_reconnect =
+            self.server.is_none() && self.server_addr.is_some() && self.session_token.is_some();
+        let attempts = self.reconnect.as_ref().map_or(0, |r| r.attempts);
+        if can_reconnect && attempts < RECONNECT_ATTEMPTS {
+            self.reconnect = Some(Reconnect {
+                attempts,
+                delay: RECONNECT_DELAY,
+            });
             return;
         }
 
-        let connection = self.client.as_mut().unwrap();
+        self.close_connection(Some(error));
+    }
+
+    // Back to the connect menu, showing why the connection was closed.
+    fn close_connection(&mut self, error: Option<String>) {
+        self.client = None;
+        self.ui.connect_error = error;
+        self.ui.show_pause_menu = false;
+        self.screen = Screen::Connect;
+        self.server = None;
+        self.reconnect = None;
+        self.session_token = None;
+    }
+
+    // Sent first in every connection, the server only accepts clients of the same build.
+    fn send_handshake(&mut self) {
+        let handshake = match Handshake::new() {
+            Ok(handshake) => handshake,
+            Err(e) => {
+                self.close_connection(Some(format!("failed to load the levels: {}", e)));
+                return;
+            }
+        };
+        if let Some(client) = self.client.as_mut() {
+            let message = bincode::serialize(&handshake).unwrap();
+            if let Err(e) = client.send_message(Channel::Reliable.id(), message) {
+                println!("error sending handshake: {}", e);
+            }
+        }
+    }
+
+    fn handle_handshake_response(&mut self, message: &[u8]) {
+        match decode_message::<HandshakeResponse>(message) {
+            Ok(HandshakeResponse::Accepted) => {
+                self.accepted = true;
+                if let Some(server_addr) = self.server_addr {
+                    self.recent_servers.add(server_addr);
+                }
+            }
+            Ok(HandshakeResponse::Rejected(reason)) => {
+                self.close_connection(Some(format!("rejected by the server: {}", reason)))
+            }
+            Err(_) => {
+                self.close_connection(Some("the server runs an incompatible version".to_string()))
+            }
+        }
+    }
+
+    // The server frees the player right away, a hosted server is shut down.
+    fn leave(&mut self) {
+        self.send_action(&ClientAction::Leave);
+        if let Some(client) = self.client.as_mut() {
+            if let Err(e) = client.send_packets() {
+                error!("{}", e);
+            }
+        }
+        if let Some(server) = self.server.as_mut() {
+            server.shutdown();
+        }
+        self.close_connection(None);
+    }
+
+    fn update_reconnect(&mut self, frame_time: f32) {
+        // Waiting for the server to answer the last attempt
+        if self.client.is_some() {
+            return;
+        }
+        let (reconnect, server_addr) = match (self.reconnect.as_mut(), self.server_addr) {
+            (Some(reconnect), Some(server_addr)) => (reconnect, server_addr),
+            _ => return,
+        };
+        reconnect.delay -= frame_time;
+        if reconnect.delay > 0. {
+            return;
+        }
+        reconnect.attempts += 1;
+        println!(
+            "Reconnecting to {}, attempt {}",
+            server_addr, reconnect.attempts
+        );
+        if let Err(e) = self.connect(server_addr) {
+            self.connection_lost(e.to_string());
+        }
+    }
+
+    fn send_action(&mut self, action: &ClientAction) {
+        if let Some(client) = self.client.as_mut() {
+            let message = bincode::serialize(action).unwrap();
+            if let Err(e) = client.send_message(Channel::Reliable.id(), message) {
+                println!("error sending message: {}", e);
+            }
+        }
+    }
+
+    fn handle_server_message(&mut self, server_message: ServerMessages) {
+        match server_message {
+            ServerMessages::ServerInfo(server_info) => {
+                self.apply_server_info(server_info);
+            }
+            ServerMessages::UpdateScore(score) => {
+                let mut player_scores = self.world.borrow::<UniqueViewMut<PlayersScore>>().unwrap();
+                *player_scores = score;
+            }
+            ServerMessages::UpdateStats(stats) => {
+                let mut players_stats = self.world.borrow::<UniqueViewMut<PlayersStats>>().unwrap();
+                *players_stats = stats;
+            }
+            ServerMessages::UpdateLobby(lobby_info) => {
+                self.lobby_info = lobby_info;
+            }
+            ServerMessages::StartGameplay => {
+                self.ui.kill_feed.clear();
+                self.screen = Screen::Gameplay;
+            }
+            ServerMessages::ChangeLevel(level) => {
+                if let Err(e) = self.change_level(level) {
+                    self.close_connection(Some(format!("failed to load the level: {}", e)));
+                }
+            }
+            ServerMessages::UpdateGameplayConfig(gameplay_config) => {
+                let mut current = self
+                    .world
+                    .borrow::<UniqueViewMut<GameplayConfig>>()
+                    .unwrap();
+                *current = gameplay_config;
+            }
+            ServerMessages::Kicked { reason } => {
+                self.close_connection(Some(format!("kicked by the server: {}", reason)));
+            }
+            ServerMessages::Shutdown => {
+                self.close_connection(Some("the server was shut down".to_string()));
+            }
+            ServerMessages::MatchOver {
+                standings,
+                team_scores,
+            } => {
+                self.standings = standings;
+                self.team_scores = team_scores;
+                self.ui.show_pause_menu = false;
+                self.ui.kill_feed.clear();
+                self.screen = Screen::Results;
+            }
+            ServerMessages::Kill { victim, killer } => {
+                self.ui.kill_feed.push(&self.lobby_info, victim, killer);
+            }
+        }
+    }
+
+    fn apply_server_info(&mut self, server_info: ServerInfo) {
+        let ServerInfo {
+            player_id,
+            session_token,
+            tick_rate,
+            scene,
+            level,
+            levels,
+            level_rotation,
+            rules,
+            score,
+            stats,
+            gameplay_config,
+        } = server_info;
         self.world
-            .run_with_data(track_client_entity, self.id)
+            .run(
+                |mut client_state: UniqueViewMut<ClientState>,
+                 mut current_tick_rate: UniqueViewMut<TickRate>,
+                 mut players_score: UniqueViewMut<PlayersScore>,
+                 mut players_stats: UniqueViewMut<PlayersStats>,
+                 mut current_gameplay_config: UniqueViewMut<GameplayConfig>| {
+                    client_state.player_id = Some(player_id);
+                    *current_tick_rate = tick_rate;
+                    *players_score = score;
+                    *players_stats = stats;
+                    *current_gameplay_config = gameplay_config;
+                },
+            )
+            .unwrap();
+
+        // The name is sent once the connection is established, with the
+        // session of the previous connection to get back the same player.
+        if !self.joined {
+            let join = ClientAction::Join(JoinRequest {
+                name: self.ui.name(),
+                session_token: self.session_token,
+            });
+            self.send_action(&join);
+            self.joined = true;
+        }
+        self.session_token = Some(session_token);
+        self.reconnect = None;
+
+        self.levels = levels;
+        self.level_rotation = level_rotation;
+        self.rules = rules;
+        // Servers of another build can send levels this client does not have
+        if let Err(e) = self.change_level(level) {
+            self.close_connection(Some(format!("failed to load the level: {}", e)));
+            return;
+        }
+
+        // Joining a running match, the player spectates until it spawns
+        if scene == Scene::Gameplay {
+            self.screen = Screen::Gameplay;
+        }
+    }
+
+    fn change_level(&mut self, level: String) -> Result<(), LevelNotFound> {
+        let level_changed = self
+            .world
+            .run(|current_level: UniqueView<CurrentLevel>| current_level.0 != level)
+            .unwrap();
+        if level_changed {
+            replace_level_collisions(&self.world, &level)?;
+            self.world
+                .run(|mut current_level: UniqueViewMut<CurrentLevel>| current_level.0 = level)
+                .unwrap();
+        }
+        Ok(())
+    }
+
+    // Shown when the server picks the next level by vote.
+    fn draw_level_vote(&mut self, y: f32) {
+        if self.level_rotation != LevelRotation::Vote {
+            return;
+        }
+        let player_id = self
+            .world
+            .borrow::<UniqueView<ClientState>>()
+            .unwrap()
+            .player_id;
+        if let Some(level) = draw_level_vote(&self.levels, &self.lobby_info, player_id, y) {
+            self.send_action(&ClientAction::VoteLevel(level));
+        }
+    }
+
+    // Frame numbers restart with each server
+    fn reset_frames(&mut self) {
+        self.frame_history.clear();
+        self.last_frame = None;
+        let mut snapshot_buffer = self
+            .world
+            .borrow::<UniqueViewMut<SnapshotBuffer>>()
             .unwrap();
+        *snapshot_buffer = SnapshotBuffer::default();
+    }
 
-        let input = self.world.run(player_input).unwrap();
-        let message = bincode::serialize(&input).expect("failed to serialize message.");
-        if let Err(e) = connection.send_message(Channel::ReliableCritical.id(), message) {
-            println!("Error sending message: {}", e);
+    fn render_gameplayer(&mut self) {
+        if self.client.is_none() {
+            return;
         }
 
+        let connection = self.client.as_mut().unwrap();
+
+        let mut received_frame = None;
+        let mut applied_frame = false;
         while let Some(message) = connection.receive_message(Channel::Unreliable.id()) {
-            let server_frame = bincode::deserialize::<ServerFrame>(&message);
-            if let Ok(server_frame) = server_frame {
-                server_frame.apply_in_world(&self.world);
-            } else {
-                println!("Error deserializing {:?}", server_frame);
+            let server_frame = ServerFrame::from_bytes(&message);
+            let server_frame = match server_frame {
+                Ok(server_frame) => server_frame,
+                Err(e) => {
+                    println!("Error deserializing {:?}", e);
+                    continue;
+                }
+            };
+
+            let server_frame = match server_frame.baseline {
+                None => server_frame,
+                Some(baseline) => match self.frame_history.get(baseline) {
+                    Some(baseline) => {
+                        match server_frame.decode_delta(baseline, &self.network_registry) {
+                            Ok(server_frame) => server_frame,
+                            Err(e) => {
+                                println!("Error decoding frame {}: {}", server_frame.frame, e);
+                                continue;
+                            }
+                        }
+                    }
+                    None => {
+                        println!(
+                            "Missing baseline {} for frame {}",
+                            baseline, server_frame.frame
+                        );
+                        continue;
+                    }
+                },
+            };
+
+            // Out of order frames are kept only as baselines
+            if self
+                .last_frame
+                .map_or(true, |last| server_frame.frame > last)
+            {
+                if let Err(e) = server_frame.apply_in_world(&self.world, &self.network_registry) {
+                    println!("Error applying frame {}: {}", server_frame.frame, e);
+                    continue;
+                }
+                self.world
+                    .run_with_data(record_snapshot, server_frame.frame)
+                    .unwrap();
+                self.last_frame = Some(server_frame.frame);
+                applied_frame = true;
             }
+            received_frame = Some(received_frame.unwrap_or(0).max(server_frame.frame));
+            self.frame_history.insert(server_frame);
+        }
+
+        if let Some(frame) = received_frame {
+            let message = bincode::serialize(&FrameAck { frame }).unwrap();
+            if let Err(e) = connection.send_message(Channel::Unreliable.id(), message) {
+                println!("Error sending message: {}", e);
+            }
+        }
+
+        self.world.run(track_client_entity).unwrap();
+        if applied_frame {
+            self.world.run(reconcile_local_player).unwrap();
         }
 
+        // The local player is simulated at the server tick rate, not at the render frame rate
+        let tick_time = self
+            .world
+            .borrow::<UniqueView<TickRate>>()
+            .unwrap()
+            .frame_time();
+        self.tick_accumulator += get_frame_time();
+        let mut input = self.world.run(player_input).unwrap();
+        if self.ui.show_pause_menu {
+            // The player stands still while the menu is open
+            input = PlayerInput {
+                direction: input.direction,
+                ..Default::default()
+            };
+        }
+        // A dash pressed between ticks is sent in the next one
+        input.dash |= self.pending_dash;
+        self.pending_dash = input.dash;
+        let mut ticks = 0;
+        while self.tick_accumulator >= tick_time {
+            if ticks == MAX_TICKS_PER_FRAME {
+                self.tick_accumulator = 0.;
+                break;
+            }
+            self.tick_accumulator -= tick_time;
+            let predicted_input = self
+                .world
+                .run_with_data(predict_local_player, input.clone())
+                .unwrap();
+            let message =
+                bincode::serialize(&predicted_input).expect("failed to serialize message.");
+            if let Err(e) = connection.send_message(Channel::ReliableCritical.id(), message) {
+                println!("Error sending message: {}", e);
+            }
+            input.dash = false;
+            self.pending_dash = false;
+            ticks += 1;
+        }
+
+        self.world
+            .run_with_data(interpolate_remote_entities, get_frame_time())
+            .unwrap();
+
         self.world.run(draw_level).unwrap();
+        if self.lobby_info.mode == GameModeKind::KingOfTheHill {
+            let hill = self.world.borrow::<UniqueView<LevelHill>>().unwrap();
+            if let Some(hill) = hill.0.as_ref() {
+                draw_hill(hill);
+            }
+        }
         self.world.run(draw_players).unwrap();
         self.world.run(draw_projectiles).unwrap();
-        self.world.run(draw_score).unwrap();
+        self.world.run(draw_team_score).unwrap();
+        self.ui.kill_feed.draw();
+        if is_key_down(KeyCode::Tab) {
+            self.world
+                .run_with_data(draw_scoreboard, &self.lobby_info)
+                .unwrap();
+        }
+        self.world.run(draw_spectating).unwrap();
 
         // Debug server physics when host
         if let Some(server) = self.server.as_ref() {
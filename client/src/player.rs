@@ -1,16 +1,18 @@
-use std::net::SocketAddr;
+use std::collections::VecDeque;
 
 use macroquad::prelude::*;
 use shared::{
     animation::AnimationController,
-    player::{Player, PlayerInput},
-    Health, Transform,
+    physics::Physics,
+    player::{update_player_movement, GameplayConfig, Player, PlayerInput},
+    Health, TickRate, Transform,
 };
 
 use shipyard::*;
 
 use crate::animation::{AnimationTextures, TextureAnimation, Textures};
-use crate::ui::mouse_to_screen;
+use crate::interpolation::SnapshotBuffer;
+use crate::ui::{mouse_to_screen, team_color};
 use crate::ClientState;
 use crate::UPSCALE;
 
@@ -31,7 +33,17 @@ pub fn draw_players(
         let flip_x =
             player.direction.angle_between(Vec2::X).abs() > std::f32::consts::PI / 2.0;
 
-        texture_animation.draw(x, y, flip_x, animation_controller);
+        // Light tint of the team color, the sprite stays readable
+        let color = player.team.map_or(WHITE, |team| {
+            let team_color = team_color(team);
+            Color::new(
+                (1. + team_color.r) / 2.,
+                (1. + team_color.g) / 2.,
+                (1. + team_color.b) / 2.,
+                1.,
+            )
+        });
+        texture_animation.draw(x, y, flip_x, color, animation_controller);
 
         // Draw wand
         let center_x = x + (texture_animation.width as f32 / 2.0);
@@ -92,6 +104,7 @@ pub fn draw_players(
 pub fn player_input(
     transforms: View<Transform>,
     client_state: UniqueView<ClientState>,
+    snapshot_buffer: UniqueView<SnapshotBuffer>,
 ) -> PlayerInput {
     if client_state.entity_id.is_none() {
         return PlayerInput::default();
@@ -100,7 +113,7 @@ pub fn player_input(
     let entity_id = client_state.entity_id.unwrap();
     let transform = transforms.get(entity_id).unwrap();
 
-    let direction = (mouse_to_screen() - (transform.position + vec2(6., 8.))).normalize();
+    let direction = (mouse_to_screen() - (transform.position + vec2(6., 8.))).normalize_or_zero();
 
     let up = is_key_down(KeyCode::W) || is_key_down(KeyCode::Up);
     let down = is_key_down(KeyCode::S) || is_key_down(KeyCode::Down);
@@ -110,6 +123,11 @@ pub fn player_input(
     let jump = is_key_down(KeyCode::Space);
     let dash = is_key_pressed(KeyCode::LeftShift);
     let fire = is_mouse_button_down(MouseButton::Left);
+    // Used by the server to rewind the targets to what we are seeing
+    let view_tick = snapshot_buffer
+        .render_tick()
+        .map_or(0, |tick| tick.round() as u64);
+
     PlayerInput {
         up,
         down,
@@ -119,27 +137,124 @@ pub fn player_input(
         fire,
         dash,
         direction,
+        view_tick,
+        ..Default::default()
+    }
+}
+
+// Inputs sent to the server that are not yet in the received frames.
+const MAX_PENDING_INPUTS: usize = 120;
+
+#[derive(Default)]
+pub struct InputPrediction {
+    sequence: u32,
+    pending_inputs: VecDeque<PlayerInput>,
+}
+
+/// Simulates the local player with the new input before the server frame arrives.
+pub fn predict_local_player(
+    mut input: PlayerInput,
+    client_state: UniqueView<ClientState>,
+    mut prediction: UniqueViewMut<InputPrediction>,
+    mut players: ViewMut<Player>,
+    mut transforms: ViewMut<Transform>,
+    mut physics: UniqueViewMut<Physics>,
+    gameplay: UniqueView<GameplayConfig>,
+    tick_rate: UniqueView<TickRate>,
+) -> PlayerInput {
+    prediction.sequence += 1;
+    input.sequence = prediction.sequence;
+
+    let entity_id = match client_state.entity_id {
+        Some(entity_id) => entity_id,
+        None => return input,
+    };
+
+    if let Ok((mut player, mut transform)) = (&mut players, &mut transforms).get(entity_id) {
+        if !physics.has_actor(entity_id) {
+            physics.add_actor(entity_id, transform.position, 8, 12);
+        }
+
+        update_player_movement(
+            entity_id,
+            &mut player,
+            &input,
+            &mut physics,
+            &gameplay,
+            tick_rate.frame_time(),
+        );
+        transform.position = physics.actor_pos(entity_id);
+
+        prediction.pending_inputs.push_back(input.clone());
+        if prediction.pending_inputs.len() > MAX_PENDING_INPUTS {
+            prediction.pending_inputs.pop_front();
+        }
+    }
+
+    input
+}
+
+/// Replays the inputs the server has not simulated yet on top of the
+/// authoritative state, should run after a server frame is applied.
+pub fn reconcile_local_player(
+    client_state: UniqueView<ClientState>,
+    mut prediction: UniqueViewMut<InputPrediction>,
+    mut players: ViewMut<Player>,
+    mut transforms: ViewMut<Transform>,
+    mut physics: UniqueViewMut<Physics>,
+    gameplay: UniqueView<GameplayConfig>,
+    tick_rate: UniqueView<TickRate>,
+) {
+    let entity_id = match client_state.entity_id {
+        Some(entity_id) => entity_id,
+        None => return,
+    };
+
+    if let Ok((mut player, mut transform)) = (&mut players, &mut transforms).get(entity_id) {
+        let acked_sequence = player.input_sequence;
+        while let Some(input) = prediction.pending_inputs.front() {
+            if input.sequence > acked_sequence {
+                break;
+            }
+            prediction.pending_inputs.pop_front();
+        }
+
+        if physics.has_actor(entity_id) {
+            physics.set_actor_position(&entity_id, transform.position);
+        } else {
+            physics.add_actor(entity_id, transform.position, 8, 12);
+        }
+
+        for input in prediction.pending_inputs.iter() {
+            update_player_movement(
+                entity_id,
+                &mut player,
+                input,
+                &mut physics,
+                &gameplay,
+                tick_rate.frame_time(),
+            );
+        }
+        transform.position = physics.actor_pos(entity_id);
     }
 }
 
 pub fn track_client_entity(
-    client_id: SocketAddr,
     mut players: ViewMut<Player>,
     mut client_state: UniqueViewMut<ClientState>,
+    mut physics: UniqueViewMut<Physics>,
 ) {
-    if client_state.client_id != client_id {
-        client_state.client_id = client_id;
-    }
     for (entity_id, player) in players.inserted().iter().with_id() {
-        if player.client_id == client_state.client_id {
+        if Some(player.player_id) == client_state.player_id {
             client_state.entity_id = Some(entity_id);
         }
     }
 
-    for (_, player) in players.take_deleted().iter() {
-        if player.client_id == client_state.client_id {
+    for (entity_id, player) in players.take_deleted().iter() {
+        if Some(player.player_id) == client_state.player_id {
             client_state.entity_id = None;
         }
+        physics.remove_actor(entity_id);
     }
 }
 
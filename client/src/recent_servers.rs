@@ -0,0 +1,40 @@
+use std::fs;
+use std::net::SocketAddr;
+
+// Saved in the working directory, one address per line.
+const RECENT_SERVERS_FILE: &str = "recent_servers.txt";
+const MAX_RECENT_SERVERS: usize = 5;
+
+/// Servers the client connected to, the most recent first, kept between runs.
+#[derive(Debug, Default)]
+pub struct RecentServers(Vec<SocketAddr>);
+
+impl RecentServers {
+    pub fn load() -> Self {
+        let servers = fs::read_to_string(RECENT_SERVERS_FILE)
+            .map(|content| {
+                content
+                    .lines()
+                    .filter_map(|line| line.trim().parse().ok())
+                    .take(MAX_RECENT_SERVERS)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self(servers)
+    }
+
+    pub fn servers(&self) -> &[SocketAddr] {
+        &self.0
+    }
+
+    pub fn add(&mut self, addr: SocketAddr) {
+        self.0.retain(|server| *server != addr);
+        self.0.insert(0, addr);
+        self.0.truncate(MAX_RECENT_SERVERS);
+
+        let content: Vec<String> = self.0.iter().map(|server| server.to_string()).collect();
+        if let Err(e) = fs::write(RECENT_SERVERS_FILE, content.join("\n")) {
+            println!("Failed to save the recent servers: {}", e);
+        }
+    }
+}
@@ -1,11 +1,19 @@
 use macroquad::prelude::*;
-use shared::math::remap;
-use shared::{ClientInfo, LobbyInfo, PlayersScore};
+use shared::discovery::DiscoveredServer;
+use shared::math::{self, remap};
+use shared::message::Standing;
+use shared::transport::{ConditionerHandle, ConditionsPreset};
+use shared::{
+    ClientInfo, GameModeKind, LobbyInfo, MatchRules, PlayerId, PlayersScore, PlayersStats, Team,
+    MAX_NAME_LENGTH,
+};
 use shipyard::UniqueView;
 
+use std::collections::VecDeque;
 use std::net::SocketAddr;
 
-use crate::{RX, RY, UPSCALE};
+use crate::interpolation::InterpolationConfig;
+use crate::{ClientState, RX, RY, UPSCALE};
 
 pub fn draw_text_upscaled(text: &str, x: f32, y: f32, font_size: f32, color: Color) {
     draw_text(text, x * UPSCALE, y * UPSCALE, font_size * UPSCALE, color);
@@ -24,11 +32,23 @@ pub fn draw_rectangle_lines_upscaled(x: f32, y: f32, w: f32, h: f32, thickness:
 
 pub struct UiState {
     pub connect_error: Option<String>,
+    // Toggled with F1.
+    pub show_network_menu: bool,
+    // Toggled with Escape during the gameplay.
+    pub show_pause_menu: bool,
+    pub kill_feed: KillFeed,
+    input_name: TextInputState,
     input_ip: TextInputState,
 }
 
 impl Default for UiState {
     fn default() -> Self {
+        let input_name = TextInputState {
+            label: "Name:".into(),
+            text: "Wizard".into(),
+            max_text_length: MAX_NAME_LENGTH,
+            ..Default::default()
+        };
         let input_ip = TextInputState {
             label: "IP:".into(),
             text: "127.0.0.1:5000".into(),
@@ -37,11 +57,22 @@ impl Default for UiState {
 
         Self {
             connect_error: None,
+            show_network_menu: false,
+            show_pause_menu: false,
+            kill_feed: KillFeed::default(),
+            input_name,
             input_ip,
         }
     }
 }
 
+impl UiState {
+    /// Name sent to the server when connecting.
+    pub fn name(&self) -> String {
+        self.input_name.text.trim().to_string()
+    }
+}
+
 struct TextInputState {
     focused: bool,
     text: String,
@@ -128,22 +159,46 @@ pub struct ConnectMenuResponse {
     pub addr: Option<SocketAddr>,
 }
 
-pub fn draw_connect_menu(ui: &mut UiState) -> ConnectMenuResponse {
+// The form is drawn left of the center to leave room for the lists of servers.
+const FORM_OFFSET: f32 = 50.;
+
+pub fn draw_connect_menu(
+    ui: &mut UiState,
+    lan_servers: &[DiscoveredServer],
+    recent_servers: &[SocketAddr],
+) -> ConnectMenuResponse {
     let mouse_position = mouse_to_screen();
-    let rect = Rect::new(RX / 2. - 50., 50.0, 150., 20.);
+    let rect = Rect::new(RX / 2. - 50. - FORM_OFFSET, 20.0, 150., 20.);
+    ui.input_name.update(rect, mouse_position);
+    ui.input_name.draw(rect);
+
+    let rect = Rect::new(RX / 2. - 50. - FORM_OFFSET, 50.0, 150., 20.);
     ui.input_ip.update(rect, mouse_position);
     ui.input_ip.draw(rect);
 
     if let Some(error) = ui.connect_error.as_ref() {
         let mut offset = 0.;
         for error_line in error.split(':') {
-            draw_text_upscaled(error_line.trim(), (RX - 100.) / 2., 82. + offset, 12., RED);
+            let x = (RX - 100.) / 2. - FORM_OFFSET;
+            draw_text_upscaled(error_line.trim(), x, 82. + offset, 12., RED);
             offset += 10.;
         }
     }
 
-    let host = draw_button(Rect::new((RX - 36.) / 2., 100.0, 36., 20.), &"host");
-    let connect = draw_button(Rect::new((RX - 58.) / 2., 130.0, 58., 20.), &"connect");
+    let host = draw_button(
+        Rect::new((RX - 36.) / 2. - FORM_OFFSET, 100.0, 36., 20.),
+        &"host",
+    );
+    let mut connect = draw_button(
+        Rect::new((RX - 58.) / 2. - FORM_OFFSET, 130.0, 58., 20.),
+        &"connect",
+    );
+
+    // Clicking a server connects to it
+    if let Some(addr) = draw_server_lists(lan_servers, recent_servers) {
+        ui.input_ip.text = addr.to_string();
+        connect = true;
+    }
 
     let ip_error = String::from("Invalid :IP address");
     let mut addr = None;
@@ -163,6 +218,65 @@ pub fn draw_connect_menu(ui: &mut UiState) -> ConnectMenuResponse {
     }
 }
 
+fn draw_server_lists(
+    lan_servers: &[DiscoveredServer],
+    recent_servers: &[SocketAddr],
+) -> Option<SocketAddr> {
+    const MAX_ENTRIES: usize = 3;
+    let x = RX - 110.;
+    let mut y = 16.;
+    let mut clicked = None;
+
+    draw_text_upscaled("LAN games", x, y, 12., WHITE);
+    y += 6.;
+    if lan_servers.is_empty() {
+        draw_text_upscaled("searching...", x + 4., y + 10., 10., GRAY);
+        y += 24.;
+    }
+    for server in lan_servers.iter().take(MAX_ENTRIES) {
+        let announcement = &server.announcement;
+        let title = format!(
+            "{} ({}/{})",
+            announcement.name, announcement.players, announcement.max_players
+        );
+        let details = if server.compatible() {
+            format!("{}, {}", announcement.mode, announcement.level)
+        } else {
+            "other version".to_string()
+        };
+        if draw_list_entry(Rect::new(x, y, 100., 20.), &title, &details) {
+            clicked = Some(server.addr);
+        }
+        y += 24.;
+    }
+
+    y += 10.;
+    draw_text_upscaled("Recent servers", x, y, 12., WHITE);
+    y += 6.;
+    for addr in recent_servers.iter().take(MAX_ENTRIES) {
+        if draw_list_entry(Rect::new(x, y, 100., 14.), &addr.to_string(), "") {
+            clicked = Some(*addr);
+        }
+        y += 18.;
+    }
+
+    clicked
+}
+
+fn draw_list_entry(rect: Rect, title: &str, details: &str) -> bool {
+    let hover = rect.contains(mouse_to_screen());
+    let clicked = hover && is_mouse_button_pressed(MouseButton::Left);
+    let color = if hover { DARKGRAY } else { WHITE };
+
+    draw_rectangle_lines_upscaled(rect.x, rect.y, rect.w, rect.h, 1.0, color);
+    draw_text_upscaled(title, rect.x + 3., rect.y + 10., 10., color);
+    if !details.is_empty() {
+        draw_text_upscaled(details, rect.x + 3., rect.y + 17., 8., GRAY);
+    }
+
+    clicked
+}
+
 pub fn mouse_to_screen() -> Vec2 {
     let mut pos: Vec2 = mouse_position().into();
 
@@ -195,19 +309,22 @@ pub fn mouse_to_screen() -> Vec2 {
     pos
 }
 
-pub fn draw_lobby(lobby_info: &LobbyInfo, id: SocketAddr) -> bool {
+pub fn draw_lobby(lobby_info: &LobbyInfo, player_id: Option<PlayerId>) -> bool {
     let mut response = false;
-    let mut clients: Vec<(&SocketAddr, &ClientInfo)> = lobby_info.clients.iter().collect();
+    let mut clients: Vec<(&PlayerId, &ClientInfo)> = lobby_info.clients.iter().collect();
     clients.sort_by(|a, b| a.0.cmp(b.0));
-    for (i, (&client_id, client_info)) in clients.iter().enumerate() {
+    for (i, (&client_player_id, client_info)) in clients.iter().enumerate() {
         let x = 10. + i as f32 * 80.;
-        draw_text_upscaled(&client_id.port().to_string(), x + 16., 20., 16., WHITE);
-        let text = if client_info.ready {
+        let color = client_info.team_pick.map_or(WHITE, team_color);
+        draw_text_upscaled(&client_info.name, x + 4., 20., 12., color);
+        let text = if !client_info.connected {
+            "reconnecting"
+        } else if client_info.ready {
             "ready"
         } else {
             "waiting"
         };
-        if client_id.port() == id.port() {
+        if Some(client_player_id) == player_id {
             response = draw_button(Rect::new(x + 5., 30., 60., 20.), &text);
         } else {
             draw_text_upscaled(&text, x + 9., 44., 16., WHITE);
@@ -216,12 +333,379 @@ pub fn draw_lobby(lobby_info: &LobbyInfo, id: SocketAddr) -> bool {
     response
 }
 
-pub fn draw_score(players_score: UniqueView<PlayersScore>) {
+// Buttons with the votes of each option, returns the index of the option clicked by the player.
+fn draw_vote(
+    title: &str,
+    options: &[(&str, usize)],
+    own_vote: Option<usize>,
+    y: f32,
+) -> Option<usize> {
+    draw_text_upscaled(title, 10., y, 12., WHITE);
+    let mut clicked = None;
+    let mut x = 10.;
+    for (i, (option, votes)) in options.iter().enumerate() {
+        let text = if own_vote == Some(i) {
+            format!("> {} ({})", option, votes)
+        } else {
+            format!("{} ({})", option, votes)
+        };
+        let width = text.len() as f32 * 8. + 8.;
+        if draw_button(Rect::new(x, y + 6., width, 20.), &text) {
+            clicked = Some(i);
+        }
+        x += width + 10.;
+    }
+    clicked
+}
+
+// Team button under the ready button of the player, returns the next pick when clicked.
+// Players without a pick are placed in a team by the server.
+pub fn draw_team_pick(lobby_info: &LobbyInfo, player_id: Option<PlayerId>) -> Option<Option<Team>> {
+    let index = lobby_info
+        .clients
+        .keys()
+        .filter(|client_player_id| Some(**client_player_id) < player_id)
+        .count();
+    let team_pick = player_id
+        .and_then(|player_id| lobby_info.clients.get(&player_id))?
+        .team_pick;
+    let text = team_pick.map_or("auto", |team| team.name());
+    let x = 10. + index as f32 * 80.;
+    if !draw_button(Rect::new(x + 5., 52., 60., 16.), text) {
+        return None;
+    }
+    let next_pick = match team_pick {
+        None => Some(Team::Red),
+        Some(Team::Red) => Some(Team::Blue),
+        Some(Team::Blue) => None,
+    };
+    Some(next_pick)
+}
+
+// Returns the level clicked by the player.
+pub fn draw_level_vote(
+    levels: &[String],
+    lobby_info: &LobbyInfo,
+    player_id: Option<PlayerId>,
+    y: f32,
+) -> Option<String> {
+    let own_vote = player_id
+        .and_then(|player_id| lobby_info.clients.get(&player_id))
+        .and_then(|client_info| client_info.level_vote.as_ref());
+    let options: Vec<(&str, usize)> = levels
+        .iter()
+        .map(|level| {
+            let votes = lobby_info
+                .clients
+                .values()
+                .filter(|client_info| client_info.level_vote.as_ref() == Some(level))
+                .count();
+            (level.as_str(), votes)
+        })
+        .collect();
+    let own_vote = levels.iter().position(|level| own_vote == Some(level));
+    draw_vote("Vote next level", &options, own_vote, y).map(|i| levels[i].clone())
+}
+
+// Short names, the titles of all the modes do not fit in a line.
+fn mode_label(mode: GameModeKind) -> &'static str {
+    match mode {
+        GameModeKind::LastWizardStanding => "LWS",
+        GameModeKind::Deathmatch => "DM",
+        GameModeKind::TeamDeathmatch => "TDM",
+        GameModeKind::KingOfTheHill => "KOTH",
+    }
+}
+
+// Returns the mode clicked by the player.
+pub fn draw_mode_vote(
+    lobby_info: &LobbyInfo,
+    player_id: Option<PlayerId>,
+    y: f32,
+) -> Option<GameModeKind> {
+    let own_vote = player_id
+        .and_then(|player_id| lobby_info.clients.get(&player_id))
+        .and_then(|client_info| client_info.mode_vote);
+    let options: Vec<(&str, usize)> = GameModeKind::ALL
+        .iter()
+        .map(|mode| {
+            let votes = lobby_info
+                .clients
+                .values()
+                .filter(|client_info| client_info.mode_vote == Some(*mode))
+                .count();
+            (mode_label(*mode), votes)
+        })
+        .collect();
+    let own_vote = GameModeKind::ALL
+        .iter()
+        .position(|mode| own_vote == Some(*mode));
+    draw_vote("Vote next mode", &options, own_vote, y).map(|i| GameModeKind::ALL[i])
+}
+
+// Mode and rules of the last match, shown in the lobby above the leave button.
+pub fn draw_match_rules(mode: GameModeKind, rules: &MatchRules) {
+    let text = format!("{}: {}", mode.title(), rules.describe());
+    draw_text_upscaled(&text, 10., RY - 40., 12., WHITE);
+}
+
+// Final standings of the match, returns true when the player goes back to the lobby.
+pub fn draw_match_results(
+    standings: &[Standing],
+    team_scores: &[(Team, u8)],
+    player_id: Option<PlayerId>,
+) -> bool {
+    let x = RX / 2. - 70.;
+    draw_text_upscaled("Match over", x, 24., 16., WHITE);
+    let mut y = 44.;
+    for (team, score) in team_scores {
+        let text = format!("{} team: {}", team.name(), score);
+        draw_text_upscaled(&text, x, y, 12., team_color(*team));
+        y += 14.;
+    }
+    for (i, standing) in standings.iter().enumerate() {
+        let text = format!(
+            "{}. {}: {}  k/d {}/{}",
+            i + 1,
+            standing.name,
+            standing.score,
+            standing.stats.kills,
+            standing.stats.deaths
+        );
+        let color = if Some(standing.player_id) == player_id {
+            YELLOW
+        } else {
+            standing.team.map_or(WHITE, team_color)
+        };
+        draw_text_upscaled(&text, x, y, 12., color);
+        y += 14.;
+    }
+    draw_button(Rect::new(RX / 2. - 30., RY - 30., 60., 20.), "lobby")
+}
+
+pub fn draw_leave_button() -> bool {
+    draw_button(Rect::new(10., RY - 30., 46., 20.), "leave")
+}
+
+pub struct PauseMenuResponse {
+    pub resume: bool,
+    pub leave: bool,
+}
+
+pub fn draw_pause_menu() -> PauseMenuResponse {
+    let x = RX / 2. - 40.;
+    let y = RY / 2. - 30.;
+    draw_rectangle(
+        x * UPSCALE,
+        y * UPSCALE,
+        80. * UPSCALE,
+        80. * UPSCALE,
+        Color::new(0., 0., 0., 0.8),
+    );
+    draw_rectangle_lines_upscaled(x, y, 80., 80., 2., WHITE);
+
+    let resume = draw_button(Rect::new(x + 10., y + 14., 60., 20.), "resume");
+    let leave = draw_button(Rect::new(x + 10., y + 46., 60., 20.), "leave");
+    PauseMenuResponse { resume, leave }
+}
+
+pub fn draw_spectating(client_state: UniqueView<ClientState>) {
+    if client_state.entity_id.is_none() {
+        let text = "Waiting for the next round";
+        draw_text_upscaled(text, RX / 2. - 60., RY - 10., 12., WHITE);
+    }
+}
+
+pub fn draw_reconnecting(attempt: u32, max_attempts: u32) {
+    let text = format!(
+        "Connection lost, reconnecting ({}/{})",
+        attempt, max_attempts
+    );
+    draw_text_upscaled(&text, RX / 2. - 80., RY / 2., 12., WHITE);
+}
+
+// Last kills of the match, each one is shown for a few seconds.
+#[derive(Default)]
+pub struct KillFeed {
+    // Text of the kill and the time it happened.
+    entries: VecDeque<(String, f64)>,
+}
+
+const KILL_FEED_SIZE: usize = 5;
+const KILL_FEED_TIME: f64 = 5.;
+
+impl KillFeed {
+    pub fn push(&mut self, lobby_info: &LobbyInfo, victim: PlayerId, killer: Option<PlayerId>) {
+        let victim = lobby_info.player_name(victim);
+        let text = match killer {
+            Some(killer) => format!("{} killed {}", lobby_info.player_name(killer), victim),
+            None => format!("{} died", victim),
+        };
+        self.entries.push_back((text, get_time()));
+        if self.entries.len() > KILL_FEED_SIZE {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn draw(&mut self) {
+        let now = get_time();
+        self.entries.retain(|(_, time)| now - time < KILL_FEED_TIME);
+        for (i, (text, _)) in self.entries.iter().enumerate() {
+            let x = RX - text.len() as f32 * 5. - 10.;
+            draw_text_upscaled(text, x, 14. + i as f32 * 12., 10., WHITE);
+        }
+    }
+}
+
+// Points of each team in the team modes.
+pub fn draw_team_score(players_score: UniqueView<PlayersScore>) {
     let mut offset_x = 0.;
-    for (client_id, score) in players_score.score.iter() {
-        let text = format!("{}: {}", client_id.port(), score);
-        draw_rectangle_lines_upscaled(10. + offset_x, 4., 50., 16., 2., WHITE);
-        draw_text_upscaled(&text, 14. + offset_x, 14., 10., WHITE);
-        offset_x += 60.;
+    for team in Team::ALL.iter() {
+        if let Some(score) = players_score.team_score.get(team) {
+            let text = format!("{}: {}", team.name(), score);
+            let width = text.len() as f32 * 5. + 8.;
+            draw_rectangle(
+                (10. + offset_x) * UPSCALE,
+                4. * UPSCALE,
+                width * UPSCALE,
+                16. * UPSCALE,
+                team_color(*team),
+            );
+            draw_text_upscaled(&text, 14. + offset_x, 14., 10., WHITE);
+            offset_x += width + 10.;
+        }
     }
 }
+
+// Score and stats of each player in the match, shown while Tab is held.
+pub fn draw_scoreboard(
+    lobby_info: &LobbyInfo,
+    players_score: UniqueView<PlayersScore>,
+    players_stats: UniqueView<PlayersStats>,
+    client_state: UniqueView<ClientState>,
+) {
+    let mut scores: Vec<(PlayerId, u8)> = players_score
+        .score
+        .iter()
+        .map(|(&player_id, &score)| (player_id, score))
+        .collect();
+    scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let x = RX / 2. - 110.;
+    let y = 30.;
+    let height = 24. + scores.len() as f32 * 12.;
+    draw_rectangle(
+        x * UPSCALE,
+        y * UPSCALE,
+        220. * UPSCALE,
+        height * UPSCALE,
+        Color::new(0., 0., 0., 0.8),
+    );
+    draw_rectangle_lines_upscaled(x, y, 220., height, 2., WHITE);
+
+    let columns = [0., 90., 120., 145., 170., 195.];
+    let header = ["name", "score", "k", "d", "k/d", "acc"];
+    for (column, text) in columns.iter().zip(header.iter()) {
+        draw_text_upscaled(text, x + 6. + column, y + 12., 10., GRAY);
+    }
+    for (i, (score_player_id, score)) in scores.into_iter().enumerate() {
+        let stats = players_stats.get(score_player_id);
+        let row = [
+            lobby_info.player_name(score_player_id),
+            score.to_string(),
+            stats.kills.to_string(),
+            stats.deaths.to_string(),
+            format!("{:.1}", stats.kill_death_ratio()),
+            format!("{:.0}%", stats.accuracy() * 100.),
+        ];
+        let color = if Some(score_player_id) == client_state.player_id {
+            YELLOW
+        } else {
+            lobby_info.team(score_player_id).map_or(WHITE, team_color)
+        };
+        let row_y = y + 24. + i as f32 * 12.;
+        for (column, text) in columns.iter().zip(row.iter()) {
+            draw_text_upscaled(text, x + 6. + column, row_y, 10., color);
+        }
+    }
+}
+
+pub fn team_color(team: Team) -> Color {
+    match team {
+        Team::Red => RED,
+        Team::Blue => SKYBLUE,
+    }
+}
+
+// Area players hold in king of the hill.
+pub fn draw_hill(hill: &math::Rect) {
+    draw_rectangle_lines_upscaled(hill.x, hill.y, hill.w, hill.h, 1., ORANGE);
+}
+
+// Label with buttons to lower or raise a value, returns the change by one step.
+fn draw_stepper(label: &str, x: f32, y: f32) -> f32 {
+    draw_text_upscaled(label, x, y + 10., 10., WHITE);
+    if draw_button(Rect::new(x + 140., y, 14., 14.), "-") {
+        -1.
+    } else if draw_button(Rect::new(x + 158., y, 14., 14.), "+") {
+        1.
+    } else {
+        0.
+    }
+}
+
+// Debug menu to simulate bad connections to the server and tune the interpolation of the
+// remote entities.
+pub fn draw_network_menu(conditions: &ConditionerHandle, interpolation: &mut InterpolationConfig) {
+    let x = 10.;
+    let y = RY - 106.;
+    draw_rectangle(
+        x * UPSCALE,
+        y * UPSCALE,
+        190. * UPSCALE,
+        100. * UPSCALE,
+        Color::new(0., 0., 0., 0.8),
+    );
+    draw_text_upscaled("Network conditions (F1)", x + 4., y + 10., 12., WHITE);
+
+    let mut offset_x = 0.;
+    for preset in ConditionsPreset::ALL.iter() {
+        let width = preset.name().len() as f32 * 7. + 8.;
+        let rect = Rect::new(x + 4. + offset_x, y + 16., width, 16.);
+        if draw_button(rect, preset.name()) {
+            conditions.set(preset.conditions());
+        }
+        offset_x += width + 4.;
+    }
+
+    let current = conditions.get();
+    let lines = [
+        format!(
+            "latency: {:.0}ms jitter: {:.0}ms",
+            current.latency, current.jitter
+        ),
+        format!(
+            "loss: {:.1}% dup: {:.1}% reorder: {:.1}%",
+            current.loss * 100.,
+            current.duplication * 100.,
+            current.reordering * 100.
+        ),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        draw_text_upscaled(line, x + 4., y + 44. + i as f32 * 10., 10., WHITE);
+    }
+
+    let delay = interpolation.delay();
+    let label = format!("interpolation: {:.0} ticks", delay);
+    let step = draw_stepper(&label, x + 4., y + 64.);
+    interpolation.set_delay(delay + step);
+
+    let max_extrapolation = interpolation.max_extrapolation();
+    let label = format!("extrapolation: {:.0} ticks", max_extrapolation);
+    let step = draw_stepper(&label, x + 4., y + 82.);
+    interpolation.set_max_extrapolation(max_extrapolation + step);
+}
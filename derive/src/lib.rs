@@ -2,7 +2,9 @@ mod network_state;
 
 use network_state::network_state_impl;
 
-#[proc_macro_derive(NetworkState)]
+/// Implements `NetworkState` for a struct, fields can use `#[network(skip)]`,
+/// `#[network(quantize = step)]` or `#[network(with = path)]` to customize the replicated state.
+#[proc_macro_derive(NetworkState, attributes(network))]
 pub fn network_state_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     network_state_impl(input)
 }
@@ -1,11 +1,127 @@
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Data, DeriveInput, Expr, Field, Fields, Ident, Path, Token,
+};
+
+enum FieldAttribute {
+    // Field is not replicated, the client keeps its own value.
+    Skip,
+    // Field is sent rounded to the given step.
+    Quantize(Expr),
+    // Field is converted with `path::to_state` and `path::from_state` into `path::State`.
+    With(Path),
+}
+
+impl Parse for FieldAttribute {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        if name == "skip" {
+            Ok(FieldAttribute::Skip)
+        } else if name == "quantize" {
+            input.parse::<Token![=]>()?;
+            Ok(FieldAttribute::Quantize(input.parse()?))
+        } else if name == "with" {
+            input.parse::<Token![=]>()?;
+            Ok(FieldAttribute::With(input.parse()?))
+        } else {
+            Err(syn::Error::new(
+                name.span(),
+                "expected `skip`, `quantize = step` or `with = path`",
+            ))
+        }
+    }
+}
+
+fn field_attribute(field: &Field) -> syn::Result<Option<FieldAttribute>> {
+    let mut field_attribute = None;
+    for attr in field.attrs.iter().filter(|a| a.path.is_ident("network")) {
+        let attributes =
+            attr.parse_args_with(Punctuated::<FieldAttribute, Token![,]>::parse_terminated)?;
+        for attribute in attributes {
+            if field_attribute.is_some() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "only one network attribute is allowed per field",
+                ));
+            }
+            field_attribute = Some(attribute);
+        }
+    }
+    Ok(field_attribute)
+}
 
 pub fn network_state_impl(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let type_name = input.ident;
 
-    let gen = quote! {
+    let result = match field_attributes(&input) {
+        Ok(Some(fields)) => state_struct_impl(&input, fields),
+        Ok(None) => Ok(self_state_impl(&input)),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(gen) => proc_macro::TokenStream::from(gen),
+        Err(e) => proc_macro::TokenStream::from(e.to_compile_error()),
+    }
+}
+
+type FieldAttributes<'a> = Vec<(&'a Field, Option<FieldAttribute>)>;
+
+// Returns None when no field uses the network attribute.
+fn field_attributes(input: &DeriveInput) -> syn::Result<Option<FieldAttributes<'_>>> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Ok(None),
+    };
+
+    let mut attributes = vec![];
+    for field in fields.iter() {
+        attributes.push((field, field_attribute(field)?));
+    }
+
+    if attributes.iter().all(|(_, attribute)| attribute.is_none()) {
+        return Ok(None);
+    }
+
+    match fields {
+        Fields::Named(_) => Ok(Some(attributes)),
+        _ => Err(syn::Error::new_spanned(
+            &input.ident,
+            "network attributes are only supported in structs with named fields",
+        )),
+    }
+}
+
+// Without field attributes the whole struct is replicated.
+fn self_state_impl(input: &DeriveInput) -> TokenStream {
+    let type_name = &input.ident;
+
+    // Named fields are sent only when they changed, other types are sent whole
+    let (delta_struct, delta_methods) = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => {
+                let fields = fields
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let ty = &field.ty;
+                        (field.ident.as_ref().unwrap(), quote! { #ty })
+                    })
+                    .collect::<Vec<_>>();
+                delta_impl(input, &quote! { Self }, &fields)
+            }
+            _ => whole_delta_impl(),
+        },
+        _ => whole_delta_impl(),
+    };
+
+    quote! {
+        #delta_struct
+
         impl crate::network::NetworkState for #type_name {
             type State = Self;
 
@@ -19,8 +135,158 @@ pub fn network_state_impl(input: proc_macro::TokenStream) -> proc_macro::TokenSt
             fn state(&self) -> Self::State {
                 self.clone()
             }
+
+            #delta_methods
+        }
+    }
+}
+
+// The delta is the whole state.
+fn whole_delta_impl() -> (TokenStream, TokenStream) {
+    let methods = quote! {
+        type Delta = Self::State;
+
+        fn delta(state: &Self::State, _baseline: Option<&Self::State>) -> Self::Delta {
+            state.clone()
+        }
+
+        fn apply_delta(
+            _baseline: Option<&Self::State>,
+            delta: Self::Delta,
+        ) -> Option<Self::State> {
+            Some(delta)
         }
     };
+    (TokenStream::new(), methods)
+}
+
+// The delta has an optional value per field of the state, so the serialized delta
+// starts with a bit per field telling if it changed since the baseline.
+fn delta_impl(
+    input: &DeriveInput,
+    state_path: &TokenStream,
+    fields: &[(&Ident, TokenStream)],
+) -> (TokenStream, TokenStream) {
+    let visibility = &input.vis;
+    let delta_name = format_ident!("{}NetworkDelta", input.ident);
+    let names = fields.iter().map(|(name, _)| name).collect::<Vec<_>>();
+    let types = fields.iter().map(|(_, ty)| ty);
 
-    proc_macro::TokenStream::from(gen)
+    let delta_struct = quote! {
+        #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+        #visibility struct #delta_name {
+            #(#names: Option<#types>,)*
+        }
+    };
+
+    let methods = quote! {
+        type Delta = #delta_name;
+
+        fn delta(state: &Self::State, baseline: Option<&Self::State>) -> Self::Delta {
+            #delta_name {
+                #(#names: match baseline {
+                    Some(baseline) if baseline.#names == state.#names => None,
+                    _ => Some(state.#names.clone()),
+                },)*
+            }
+        }
+
+        fn apply_delta(
+            baseline: Option<&Self::State>,
+            delta: Self::Delta,
+        ) -> Option<Self::State> {
+            Some(#state_path {
+                #(#names: match delta.#names {
+                    Some(value) => value,
+                    None => baseline?.#names.clone(),
+                },)*
+            })
+        }
+    };
+
+    (delta_struct, methods)
+}
+
+fn state_struct_impl(input: &DeriveInput, fields: FieldAttributes<'_>) -> syn::Result<TokenStream> {
+    let type_name = &input.ident;
+    let visibility = &input.vis;
+    let state_name = format_ident!("{}NetworkState", type_name);
+
+    let mut state_fields = vec![];
+    let mut delta_fields = vec![];
+    let mut from_state = vec![];
+    let mut update_from_state = vec![];
+    let mut state = vec![];
+
+    for (field, attribute) in fields {
+        let name = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+
+        match attribute {
+            Some(FieldAttribute::Skip) => {
+                from_state.push(quote! { #name: Default::default() });
+            }
+            Some(FieldAttribute::Quantize(step)) => {
+                let state_ty = quote! { <#ty as crate::network::Quantize>::Quantized };
+                state_fields.push(quote! { #name: #state_ty });
+                delta_fields.push((name, state_ty));
+                from_state.push(quote! {
+                    #name: <#ty as crate::network::Quantize>::dequantize(state.#name, #step)
+                });
+                update_from_state.push(quote! {
+                    self.#name = <#ty as crate::network::Quantize>::dequantize(state.#name, #step);
+                });
+                state.push(quote! {
+                    #name: crate::network::Quantize::quantize(&self.#name, #step)
+                });
+            }
+            Some(FieldAttribute::With(path)) => {
+                state_fields.push(quote! { #name: #path::State });
+                delta_fields.push((name, quote! { #path::State }));
+                from_state.push(quote! { #name: #path::from_state(state.#name) });
+                update_from_state.push(quote! { self.#name = #path::from_state(state.#name); });
+                state.push(quote! { #name: #path::to_state(&self.#name) });
+            }
+            None => {
+                state_fields.push(quote! { #name: #ty });
+                delta_fields.push((name, quote! { #ty }));
+                from_state.push(quote! { #name: state.#name });
+                update_from_state.push(quote! { self.#name = state.#name; });
+                state.push(quote! { #name: self.#name.clone() });
+            }
+        }
+    }
+
+    let (delta_struct, delta_methods) = delta_impl(input, &quote! { #state_name }, &delta_fields);
+
+    Ok(quote! {
+        #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+        #visibility struct #state_name {
+            #(#state_fields,)*
+        }
+
+        #delta_struct
+
+        impl crate::network::NetworkState for #type_name {
+            type State = #state_name;
+
+            fn from_state(state: Self::State) -> Self {
+                Self {
+                    #(#from_state,)*
+                }
+            }
+
+            fn update_from_state(&mut self, state: Self::State) {
+                #(#update_from_state)*
+            }
+
+            fn state(&self) -> Self::State {
+                #state_name {
+                    #(#state,)*
+                }
+            }
+
+            #delta_methods
+        }
+    })
 }
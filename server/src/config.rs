@@ -0,0 +1,294 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use shared::ldtk::{LevelRotation, DEFAULT_LEVEL};
+use shared::transport::ConditionsPreset;
+use shared::{GameModeKind, MatchRules};
+
+pub const USAGE: &str = "Usage: server [OPTIONS]
+
+Options:
+    --config <path>        Read the options from a file with `key = value` lines
+    --address <addr>       Address the server binds to [default: 127.0.0.1:5000]
+    --name <name>          Name shown in the list of LAN games [default: Wizardfall]
+    --max-clients <n>      Maximum connected clients [default: 64]
+    --tick-rate <n>        Simulation steps per second [default: 60]
+    --send-rate <n>        Server frames sent per second [default: 30]
+    --level <identifier>   LDtk level of the first round [default: First]
+    --levels <identifiers> Comma separated levels played between rounds [default: all levels]
+    --level-rotation <mode>
+                           How the next level is picked: fixed, sequential, random or vote
+                           [default: sequential]
+    --mode <mode>          Mode of the matches when the players do not vote for one:
+                           last-wizard-standing, deathmatch, team-deathmatch or
+                           king-of-the-hill [default: last-wizard-standing]
+    --friendly-fire <bool> Fireballs hurt teammates in the team modes [default: false]
+    --score-limit <n>      Rounds to win the match, 0 for no limit [default: 5]
+    --max-rounds <n>       Rounds played in a match, 0 for no limit [default: 0]
+    --time-limit <seconds> Duration of a match, 0 for no limit [default: 0]
+    --late-join-spawn <bool>
+                           Spawn players joining a running round right away [default: false]
+    --reconnect-timeout <seconds>
+                           Time disconnected players keep their slot and score [default: 30]
+    --keep-disconnected-players <bool>
+                           Keep disconnected players in the round while they can reconnect
+                           [default: false]
+    --max-strikes <n>      Bad or excessive messages before a client is kicked [default: 10]
+    --max-inputs-per-second <n>
+                           Inputs accepted from each client per second [default: 120]
+    --max-actions-per-second <n>
+                           Lobby actions accepted from each client per second [default: 10]
+    --network-conditions <preset>
+                           Simulated connection: off, lan, wifi or mobile [default: off]
+    --headless             Run without the configuration window
+    --help                 Print this message
+
+The same keys can be used in the config file, with underscores (max_clients = 8),
+options passed in the command line override the ones in the file.";
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // Shown to the clients looking for games in the local network.
+    pub name: String,
+    // Simulation steps per second.
+    pub tick_rate: u32,
+    // Server frames sent per second, can not be higher than the tick rate.
+    pub send_rate: u32,
+    pub max_clients: usize,
+    // Identifier of the LDtk level of the first round.
+    pub level: String,
+    // Pool of levels picked from between rounds, empty for all the levels in the project.
+    pub levels: Vec<String>,
+    pub level_rotation: LevelRotation,
+    // Mode of the matches, unless the players vote for another one in the lobby.
+    pub mode: GameModeKind,
+    // Fireballs hurt the teammates of the caster in the team modes.
+    pub friendly_fire: bool,
+    // When the match ends and the players go back to the lobby.
+    pub rules: MatchRules,
+    // Spawn players joining a running round right away instead of in the next round.
+    pub late_join_spawn: bool,
+    // Seconds a disconnected player can take to reconnect and keep its slot and score,
+    // zero removes players as soon as they disconnect.
+    pub reconnect_timeout: u32,
+    // Keep the entity of disconnected players in the round while they can reconnect.
+    pub keep_disconnected_players: bool,
+    // Bad messages, or seconds over the rate limits, tolerated before kicking a client.
+    pub max_strikes: u32,
+    // Messages accepted from each client per second, the rest are dropped.
+    pub max_inputs_per_second: u32,
+    pub max_actions_per_second: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            name: "Wizardfall".to_string(),
+            tick_rate: 60,
+            send_rate: 30,
+            max_clients: 64,
+            level: DEFAULT_LEVEL.to_string(),
+            levels: vec![],
+            level_rotation: LevelRotation::Sequential,
+            mode: GameModeKind::default(),
+            friendly_fire: false,
+            rules: MatchRules::default(),
+            late_join_spawn: false,
+            reconnect_timeout: 30,
+            keep_disconnected_players: false,
+            max_strikes: 10,
+            max_inputs_per_second: 120,
+            max_actions_per_second: 10,
+        }
+    }
+}
+
+impl ServerConfig {
+    // Amount of ticks between each server frame sent.
+    pub(crate) fn send_interval(&self) -> u64 {
+        (self.tick_rate / self.send_rate.max(1)).max(1) as u64
+    }
+
+    // Amount of ticks between each stats update, one second.
+    pub(crate) fn stats_interval(&self) -> u64 {
+        self.tick_rate.max(1) as u64
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, io::Error),
+    InvalidLine(String, usize),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "failed to read config file {}: {}", path, e),
+            ConfigError::InvalidLine(path, line) => {
+                write!(f, "invalid line {} in config file {}", line, path)
+            }
+            ConfigError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            ConfigError::MissingValue(option) => write!(f, "missing value for option {}", option),
+            ConfigError::InvalidValue(option, value) => {
+                write!(f, "invalid value {} for option {}", value, option)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Options of the dedicated server binary.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub address: SocketAddr,
+    pub headless: bool,
+    // Simulated conditions of the connections, for testing.
+    pub network_conditions: ConditionsPreset,
+    pub config: ServerConfig,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:5000".parse().unwrap(),
+            headless: false,
+            network_conditions: ConditionsPreset::Off,
+            config: ServerConfig::default(),
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::InvalidValue(key.to_string(), value.to_string()))
+}
+
+impl ServerOptions {
+    /// Parses the command line arguments, without the program name.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut config_file = None;
+        let mut options = vec![];
+
+        while let Some(arg) = args.next() {
+            let key = match arg.strip_prefix("--") {
+                Some(key) => key.replace('-', "_"),
+                None => return Err(ConfigError::UnknownOption(arg)),
+            };
+            if key == "headless" {
+                options.push((key, "true".to_string()));
+                continue;
+            }
+
+            let value = args.next().ok_or_else(|| ConfigError::MissingValue(arg))?;
+            if key == "config" {
+                config_file = Some(value);
+            } else {
+                options.push((key, value));
+            }
+        }
+
+        let mut server_options = ServerOptions::default();
+        if let Some(path) = config_file {
+            server_options.load_file(&path)?;
+        }
+        for (key, value) in options.iter() {
+            server_options.set(key, value)?;
+        }
+        server_options.validate()?;
+
+        Ok(server_options)
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), ConfigError> {
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match line.split_once('=') {
+                Some((key, value)) => self.set(key.trim(), value.trim())?,
+                None => return Err(ConfigError::InvalidLine(path.to_string(), i + 1)),
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match key {
+            "address" => self.address = parse(key, value)?,
+            "headless" => self.headless = parse(key, value)?,
+            "network_conditions" => {
+                self.network_conditions = ConditionsPreset::from_name(value)
+                    .ok_or_else(|| ConfigError::InvalidValue(key.to_string(), value.to_string()))?
+            }
+            "name" => self.config.name = value.to_string(),
+            "max_clients" => self.config.max_clients = parse(key, value)?,
+            "tick_rate" => self.config.tick_rate = parse(key, value)?,
+            "send_rate" => self.config.send_rate = parse(key, value)?,
+            "level" => self.config.level = value.to_string(),
+            "levels" => {
+                self.config.levels = value
+                    .split(',')
+                    .map(|level| level.trim().to_string())
+                    .filter(|level| !level.is_empty())
+                    .collect()
+            }
+            "level_rotation" => {
+                self.config.level_rotation = LevelRotation::from_name(value)
+                    .ok_or_else(|| ConfigError::InvalidValue(key.to_string(), value.to_string()))?
+            }
+            "mode" => {
+                self.config.mode = GameModeKind::from_name(value)
+                    .ok_or_else(|| ConfigError::InvalidValue(key.to_string(), value.to_string()))?
+            }
+            "friendly_fire" => self.config.friendly_fire = parse(key, value)?,
+            "score_limit" => self.config.rules.score_limit = parse(key, value)?,
+            "max_rounds" => self.config.rules.max_rounds = parse(key, value)?,
+            "time_limit" => self.config.rules.time_limit = parse(key, value)?,
+            "late_join_spawn" => self.config.late_join_spawn = parse(key, value)?,
+            "reconnect_timeout" => self.config.reconnect_timeout = parse(key, value)?,
+            "keep_disconnected_players" => {
+                self.config.keep_disconnected_players = parse(key, value)?
+            }
+            "max_strikes" => self.config.max_strikes = parse(key, value)?,
+            "max_inputs_per_second" => self.config.max_inputs_per_second = parse(key, value)?,
+            "max_actions_per_second" => self.config.max_actions_per_second = parse(key, value)?,
+            _ => return Err(ConfigError::UnknownOption(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let config = &self.config;
+        if config.max_clients == 0 {
+            return Err(ConfigError::InvalidValue(
+                "max_clients".to_string(),
+                config.max_clients.to_string(),
+            ));
+        }
+        if config.tick_rate == 0 {
+            return Err(ConfigError::InvalidValue(
+                "tick_rate".to_string(),
+                config.tick_rate.to_string(),
+            ));
+        }
+        if config.send_rate == 0 || config.send_rate > config.tick_rate {
+            return Err(ConfigError::InvalidValue(
+                "send_rate".to_string(),
+                config.send_rate.to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
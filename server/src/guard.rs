@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use crate::ServerConfig;
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Tracks the misbehaviour of a connection, bad messages and messages over
+/// the rate limits are counted as strikes until the client is kicked.
+#[derive(Debug, Default)]
+pub(crate) struct ClientGuard {
+    strikes: u32,
+    // Messages received in the current window.
+    inputs: u32,
+    actions: u32,
+    window: Duration,
+    // Only one strike is given for going over the limits in a window.
+    limited: bool,
+}
+
+impl ClientGuard {
+    pub fn update(&mut self, duration: Duration) {
+        self.window += duration;
+        if self.window >= RATE_WINDOW {
+            self.window = Duration::ZERO;
+            self.inputs = 0;
+            self.actions = 0;
+            self.limited = false;
+        }
+    }
+
+    // Returns false when the input should be dropped.
+    pub fn allow_input(&mut self, config: &ServerConfig) -> bool {
+        self.inputs += 1;
+        let allowed = self.inputs <= config.max_inputs_per_second;
+        self.limit(allowed)
+    }
+
+    // Returns false when the action should be dropped.
+    pub fn allow_action(&mut self, config: &ServerConfig) -> bool {
+        self.actions += 1;
+        let allowed = self.actions <= config.max_actions_per_second;
+        self.limit(allowed)
+    }
+
+    pub fn strike(&mut self) {
+        self.strikes += 1;
+    }
+
+    pub fn should_kick(&self, config: &ServerConfig) -> bool {
+        self.strikes > config.max_strikes
+    }
+
+    fn limit(&mut self, allowed: bool) -> bool {
+        if !allowed && !self.limited {
+            self.limited = true;
+            self.strike();
+        }
+        allowed
+    }
+}
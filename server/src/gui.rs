@@ -0,0 +1,105 @@
+use eframe::{egui, epi};
+use shipyard::UniqueViewMut;
+
+use server::Game;
+use shared::player::GameplayConfig;
+use shared::transport::{ConditionerHandle, ConditionsPreset};
+
+struct ServerApp {
+    game: Game,
+    conditions: ConditionerHandle,
+    preset: ConditionsPreset,
+}
+
+pub fn run(game: Game, conditions: ConditionerHandle, preset: ConditionsPreset) -> ! {
+    let server_app = ServerApp {
+        game,
+        conditions,
+        preset,
+    };
+    eframe::run_native(Box::new(server_app));
+}
+
+impl epi::App for ServerApp {
+    fn name(&self) -> &str {
+        "Server"
+    }
+
+    fn update(&mut self, ctx: &egui::CtxRef, frame: &mut epi::Frame<'_>) {
+        self.game.update();
+
+        ctx.request_repaint();
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.game.world.run(|mut config: UniqueViewMut<GameplayConfig>| {
+                ui.heading("Gameplay Configuration:");
+                let grid = egui::Grid::new("my_grid")
+                    .striped(true)
+                    .spacing([40.0, 4.0]);
+                grid.show(ui, |ui| {
+                    ui.label("Dash speed:");
+                    ui.add(egui::Slider::f32(&mut config.dash_speed, 0.0..=1000.0).text("value"));
+                    ui.end_row();
+
+                    ui.label("Jump speed:");
+                    ui.add(egui::Slider::f32(&mut config.jump_speed, 0.0..=1000.0).text("value"));
+                    ui.end_row();
+
+                    ui.label("Walk speed:");
+                    ui.add(egui::Slider::f32(&mut config.walk_speed, 0.0..=1000.0).text("value"));
+                    ui.end_row();
+
+                    ui.label("Player gravity:");
+                    ui.add(egui::Slider::f32(&mut config.player_gravity, 0.0..=1000.0).text("value"));
+                    ui.end_row();
+                });
+            }).unwrap();
+
+            ui.separator();
+            ui.heading("Network Conditions:");
+            ui.horizontal(|ui| {
+                let previous_preset = self.preset;
+                for &preset in ConditionsPreset::ALL.iter() {
+                    ui.radio_value(&mut self.preset, preset, preset.name());
+                }
+                if self.preset != previous_preset {
+                    self.conditions.set(self.preset.conditions());
+                }
+            });
+
+            let mut conditions = self.conditions.get();
+            let grid = egui::Grid::new("network_conditions")
+                .striped(true)
+                .spacing([40.0, 4.0]);
+            grid.show(ui, |ui| {
+                ui.label("Latency:");
+                ui.add(egui::Slider::f32(&mut conditions.latency, 0.0..=500.0).text("ms"));
+                ui.end_row();
+
+                ui.label("Jitter:");
+                ui.add(egui::Slider::f32(&mut conditions.jitter, 0.0..=200.0).text("ms"));
+                ui.end_row();
+
+                ui.label("Loss:");
+                ui.add(egui::Slider::f32(&mut conditions.loss, 0.0..=1.0).text("chance"));
+                ui.end_row();
+
+                ui.label("Duplication:");
+                ui.add(egui::Slider::f32(&mut conditions.duplication, 0.0..=1.0).text("chance"));
+                ui.end_row();
+
+                ui.label("Reordering:");
+                ui.add(egui::Slider::f32(&mut conditions.reordering, 0.0..=1.0).text("chance"));
+                ui.end_row();
+            });
+            self.conditions.set(conditions);
+        });
+
+        // Resize the native window to be just the size we need it to be:
+        frame.set_window_size(ctx.used_size());
+    }
+
+    fn on_exit(&mut self) {
+        self.game.shutdown();
+    }
+}
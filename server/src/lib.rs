@@ -1,98 +1,148 @@
 use shared::{
     animation::{AnimationController, AnimationEntity},
-    channels_config,
-    ldtk::{load_level_collisions, PlayerRespawnPoints},
-    message::{ClientAction, ServerMessages},
-    network::ServerFrame,
+    discovery::{DiscoveryResponder, ServerAnnouncement},
+    ldtk::{
+        level_identifiers, load_level_collisions, replace_level_collisions, LevelHill,
+        LevelRotation, PlayerRespawnPoints,
+    },
+    message::{
+        content_hash, decode_message, ClientAction, FrameAck, Handshake, HandshakeResponse,
+        JoinRequest, ServerInfo, ServerMessages, Standing, PROTOCOL_VERSION,
+    },
+    network::{register_components, FrameHistory, NetworkRegistry, ServerFrame},
     physics::Physics,
-    player::{Player, PlayerInput},
+    player::{update_player_movement, GameplayConfig, Player, PlayerInput},
     projectile::{Projectile, ProjectileType},
-    timer::Timer,
-    Channel, ClientInfo, Health, LobbyInfo, PlayersScore, Transform,
+    timer::TimerSimple,
+    transport::{ServerEvent, ServerTransport},
+    Channel, ClientInfo, GameModeKind, Health, LobbyInfo, PlayerId, PlayersScore, PlayersStats,
+    SessionToken, Team, TickRate, Transform, MAX_NAME_LENGTH,
 };
 
-use renet_udp::{
-    renet::remote_connection::ConnectionConfig,
-    server::{ServerEvent, UdpServer},
-};
-
-use bincode::{deserialize, serialize};
+use bincode::serialize;
+use log::{error, info};
 
 use glam::{vec2, Vec2};
 use shipyard::*;
 
-use std::net::UdpSocket;
+use std::collections::{HashMap, VecDeque};
+use std::io;
 use std::time::Duration;
-use std::{collections::HashMap, io};
 use std::{net::SocketAddr, time::Instant};
 
-enum Scene {
-    Lobby,
-    Gameplay,
-}
+pub mod config;
+mod guard;
+mod mode;
+mod random;
+
+pub use config::ServerConfig;
+pub use shared::message::Scene;
+
+use guard::ClientGuard;
+use mode::{new_game_mode, Death, GameMode, RoundState};
+use random::{random_index, random_u64};
+
+// When the server falls behind more than this, the remaining time is dropped.
+const MAX_TICKS_PER_UPDATE: u32 = 5;
+// Time for a rejected client to receive the reason before it is disconnected.
+const CLOSE_DELAY: Duration = Duration::from_secs(1);
 
 pub struct Game {
     pub world: World,
+    config: ServerConfig,
     scene: Scene,
-    server: UdpServer,
+    server: Box<dyn ServerTransport>,
     last_updated: Instant,
+    // Time not yet simulated.
+    accumulator: Duration,
     lobby_info: LobbyInfo,
     lobby_updated: bool,
+    network_registry: NetworkRegistry,
+    frame_history: FrameHistory,
+    // Last frame acknowledged by each client, used as their delta baseline.
+    client_acks: HashMap<SocketAddr, u64>,
+    // Identity of the player behind each connection.
+    players: HashMap<SocketAddr, PlayerId>,
+    next_player_id: u32,
+    // Player of each session, kept while the player is connected or held.
+    sessions: HashMap<SessionToken, PlayerId>,
+    // Players disconnected that can still reconnect, with the time left to do so.
+    held_sessions: HashMap<PlayerId, Duration>,
+    client_guards: HashMap<SocketAddr, ClientGuard>,
+    // Clients told why they are disconnected, closed after a moment so the message arrives.
+    closing_clients: HashMap<SocketAddr, Duration>,
+    // Must match the one of the clients, see `Handshake`.
+    content_hash: u64,
+    // Gameplay values last sent to the clients, sent again when they change.
+    gameplay_config: GameplayConfig,
+    // Answers the clients looking for games in the local network, with the game port.
+    discovery: Option<(DiscoveryResponder, u16)>,
+    // Levels picked from between rounds, the current one is `config.level`.
+    level_pool: Vec<String>,
+    // Rounds finished and gameplay time of the current match, see `MatchRules`.
+    rounds: u32,
+    match_time: Duration,
+    mode: Box<dyn GameMode>,
+    // Dead players waiting to spawn again in the running round, in the modes with respawns.
+    respawns: HashMap<PlayerId, Duration>,
 }
 
 struct GameplayInfo {
     respawn_players: bool,
-    respawn_players_timer: Timer,
+    respawn_players_timer: TimerSimple,
 }
 
-#[derive(Debug)]
-pub struct GameplayConfig {
-    pub dash_speed: f32,
-    pub jump_speed: f32,
-    pub walk_speed: f32,
-    pub player_gravity: f32,
-    pub dash_duration: f32,
-    pub dash_cooldown: f32,
-    pub fireball_cooldown: f32,
-}
+type PlayerMapping = HashMap<PlayerId, EntityId>;
 
-impl Default for GameplayConfig {
-    fn default() -> Self {
-        Self {
-            dash_speed: 160.,
-            jump_speed: 180.,
-            walk_speed: 80.,
-            player_gravity: 550.,
-            dash_duration: 0.,
-            dash_cooldown: 0.,
-            fireball_cooldown: 0.,
-        }
-    }
-}
+// Whether fireballs hit the teammates of the caster.
+struct FriendlyFire(bool);
+
+// Current gameplay tick, also used as the server frame number.
+#[derive(Debug, Default)]
+struct ServerTick(u64);
 
-type PlayerMapping = HashMap<SocketAddr, EntityId>;
+// Inputs received from the client that were not simulated yet,
+// one input is consumed each frame so the client prediction matches.
+#[derive(Default)]
+struct PlayerInputQueue(VecDeque<PlayerInput>);
+
+const MAX_QUEUED_INPUTS: usize = 8;
 
 impl Game {
-    pub fn new(addr: SocketAddr) -> Result<Self, io::Error> {
-        let socket = UdpSocket::bind(addr)?;
-        let connection_config = ConnectionConfig {
-            channels_config: channels_config(),
-            ..Default::default()
+    /// Fails when the files of the levels can not be read or the level is not in them.
+    pub fn new(server: Box<dyn ServerTransport>, config: ServerConfig) -> Result<Self, io::Error> {
+        let mut world = World::new();
+        load_level_collisions(&mut world, &config.level)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+        let level_pool = if config.levels.is_empty() {
+            level_identifiers()
+        } else {
+            config.levels.clone()
         };
-        let server: UdpServer = UdpServer::new(64, connection_config, socket).unwrap();
 
-        let mut world = World::new();
-        load_level_collisions(&mut world);
+        let lobby_info = LobbyInfo {
+            mode: config.mode,
+            ..Default::default()
+        };
+        let mode = new_game_mode(config.mode);
 
         let server_info = GameplayInfo {
             respawn_players: false,
-            respawn_players_timer: Timer::new(Duration::from_secs(3)),
+            respawn_players_timer: TimerSimple::new(3.),
         };
 
         world.add_unique(server_info).unwrap();
         world.add_unique(PlayerMapping::new()).unwrap();
         world.add_unique(PlayersScore::default()).unwrap();
-        world.add_unique(GameplayConfig::default()).unwrap();
+        world.add_unique(PlayersStats::default()).unwrap();
+        let gameplay_config = GameplayConfig::default();
+        let content_hash = content_hash()?;
+        world.add_unique(gameplay_config.clone()).unwrap();
+        world.add_unique(ServerTick::default()).unwrap();
+        world.add_unique(TickRate(config.tick_rate)).unwrap();
+        world
+            .add_unique(FriendlyFire(config.friendly_fire))
+            .unwrap();
 
         world.borrow::<ViewMut<Player>>().unwrap().track_deletion();
         world
@@ -100,127 +150,310 @@ impl Game {
             .unwrap()
             .track_deletion();
 
+        let network_registry = register_components();
+
         Ok(Self {
             world,
+            config,
             server,
             scene: Scene::Lobby,
             last_updated: Instant::now(),
-            lobby_info: LobbyInfo::default(),
+            accumulator: Duration::ZERO,
+            lobby_info,
             lobby_updated: false,
+            network_registry,
+            frame_history: FrameHistory::default(),
+            client_acks: HashMap::new(),
+            players: HashMap::new(),
+            next_player_id: 0,
+            sessions: HashMap::new(),
+            held_sessions: HashMap::new(),
+            client_guards: HashMap::new(),
+            closing_clients: HashMap::new(),
+            content_hash,
+            gameplay_config,
+            discovery: None,
+            level_pool,
+            rounds: 0,
+            match_time: Duration::ZERO,
+            mode,
+            respawns: HashMap::new(),
         })
     }
 
+    /// Lists the game in the LAN server browser of the clients, the port is the one
+    /// the clients connect to.
+    pub fn enable_discovery(&mut self, port: u16) {
+        match DiscoveryResponder::new() {
+            Ok(responder) => self.discovery = Some((responder, port)),
+            // Only one server in each machine can answer the queries
+            Err(e) => error!("Failed to enable LAN discovery: {}", e),
+        }
+    }
+
+    pub fn scene(&self) -> Scene {
+        self.scene
+    }
+
     pub fn update(&mut self) {
-        self.lobby_updated = false;
         let now = Instant::now();
         let frame_duration = now - self.last_updated;
         self.last_updated = now;
+        self.update_with_duration(frame_duration);
+    }
+
+    /// Advances the server by the given time instead of the time elapsed since the last update,
+    /// used to simulate the server deterministically.
+    pub fn update_with_duration(&mut self, frame_duration: Duration) {
+        self.lobby_updated = false;
+        self.check_gameplay_config();
         if let Err(e) = self.server.update(frame_duration) {
-            println!("{}", e);
+            error!("{}", e);
         }
+        let mut kicked_clients = vec![];
         for client_id in self.server.clients_id().iter() {
+            if self.closing_clients.contains_key(client_id) {
+                continue;
+            }
+            // Clients play once their handshake is accepted
+            let player_id = match self.players.get(client_id) {
+                Some(player_id) => *player_id,
+                None => {
+                    self.read_handshake(client_id);
+                    continue;
+                }
+            };
+            let guard = self.client_guards.entry(*client_id).or_default();
+            guard.update(frame_duration);
+
             while let Some(message) = self
                 .server
                 .receive_message(client_id, Channel::ReliableCritical.id())
             {
-                let input: PlayerInput = deserialize(&message).expect("Failed to deserialize.");
+                if !guard.allow_input(&self.config) {
+                    continue;
+                }
+                let input = decode_message::<PlayerInput>(&message)
+                    .and_then(|input| input.validate().map(|_| input));
+                let input = match input {
+                    Ok(input) => input,
+                    Err(e) => {
+                        info!("Bad input from client {}: {}", client_id, e);
+                        guard.strike();
+                        continue;
+                    }
+                };
                 self.world
                     .run(
                         |player_mapping: UniqueView<PlayerMapping>,
-                         mut inputs: ViewMut<PlayerInput>| {
-                            if let Some(entity_id) = player_mapping.get(client_id) {
-                                inputs.add_component_unchecked(*entity_id, input);
+                         mut input_queues: ViewMut<PlayerInputQueue>| {
+                            if let Some(entity_id) = player_mapping.get(&player_id) {
+                                if let Ok(mut input_queue) = (&mut input_queues).get(*entity_id) {
+                                    input_queue.0.push_back(input);
+                                    if input_queue.0.len() > MAX_QUEUED_INPUTS {
+                                        input_queue.0.pop_front();
+                                    }
+                                }
                             }
                         },
                     )
                     .unwrap();
             }
 
+            let mut actions = vec![];
             while let Some(message) = self
                 .server
                 .receive_message(client_id, Channel::Reliable.id())
             {
-                let player_action: ClientAction = deserialize(&message).unwrap();
-                self.handle_client_action(player_action, client_id);
+                if !guard.allow_action(&self.config) {
+                    continue;
+                }
+                match decode_message::<ClientAction>(&message) {
+                    Ok(action) => actions.push(action),
+                    Err(e) => {
+                        info!("Bad action from client {}: {}", client_id, e);
+                        guard.strike();
+                    }
+                }
             }
+
+            while let Some(message) = self
+                .server
+                .receive_message(client_id, Channel::Unreliable.id())
+            {
+                match decode_message::<FrameAck>(&message) {
+                    Ok(FrameAck { frame }) => {
+                        let last_ack = self.client_acks.entry(*client_id).or_insert(frame);
+                        *last_ack = (*last_ack).max(frame);
+                    }
+                    Err(e) => {
+                        info!("Bad frame ack from client {}: {}", client_id, e);
+                        guard.strike();
+                    }
+                }
+            }
+
+            if guard.should_kick(&self.config) {
+                kicked_clients.push(*client_id);
+                continue;
+            }
+            for action in actions {
+                self.handle_client_action(action, client_id);
+            }
+        }
+        for client_id in kicked_clients {
+            self.kick(&client_id, "too many bad messages");
         }
 
         while let Some(event) = self.server.get_event() {
             match event {
-                ServerEvent::ClientConnected(id) => {
-                    self.lobby_info.clients.insert(id, ClientInfo::default());
-                    self.lobby_updated = true;
-
-                    self.world
-                        .run(|mut players_score: UniqueViewMut<PlayersScore>| {
-                            players_score.score.insert(id, 0);
-                            players_score.updated = true;
-                        })
-                        .unwrap();
+                ServerEvent::ClientConnected(client_id) => {
+                    info!("Client {} connected", client_id);
                 }
-                ServerEvent::ClientDisconnected(id, _) => {
-                    self.lobby_info.clients.remove(&id);
-                    self.lobby_updated = true;
-
-                    self.world.run_with_data(remove_player, id).unwrap();
-                    self.world
-                        .run(|mut players_score: UniqueViewMut<PlayersScore>| {
-                            players_score.score.remove(&id);
-                            players_score.updated = true;
-                        })
-                        .unwrap();
+                ServerEvent::ClientDisconnected(client_id, reason) => {
+                    info!("Client {} disconnected: {:?}", client_id, reason);
+                    self.client_acks.remove(&client_id);
+                    self.client_guards.remove(&client_id);
+                    self.closing_clients.remove(&client_id);
+                    if let Some(player_id) = self.players.remove(&client_id) {
+                        self.hold_session(player_id);
+                    }
                 }
             }
         }
 
+        let mut closed_clients = vec![];
+        for (client_id, time_left) in self.closing_clients.iter_mut() {
+            match time_left.checked_sub(frame_duration) {
+                Some(remaining) => *time_left = remaining,
+                None => closed_clients.push(*client_id),
+            }
+        }
+        for client_id in closed_clients {
+            self.closing_clients.remove(&client_id);
+            self.server.disconnect(&client_id);
+        }
+
+        // Sessions not resumed in time are removed
+        let mut expired_sessions = vec![];
+        for (player_id, time_left) in self.held_sessions.iter_mut() {
+            match time_left.checked_sub(frame_duration) {
+                Some(remaining) => *time_left = remaining,
+                None => expired_sessions.push(*player_id),
+            }
+        }
+        for player_id in expired_sessions {
+            info!("Session of player {} expired", player_id);
+            self.remove_session(player_id);
+        }
+
+        // Names can change during the gameplay
+        if self.lobby_updated {
+            let lobby_update = ServerMessages::UpdateLobby(self.lobby_info.clone());
+            self.broadcast(&lobby_update);
+        }
+
         match self.scene {
             Scene::Lobby => {
-                let start_lobby = self.lobby_info.clients.len() > 1
-                    && self.lobby_info.clients.values().all(|c| c.ready);
+                // Players reconnecting do not hold the lobby
+                let connected_clients = self.lobby_info.clients.values().filter(|c| c.connected);
+                let start_lobby =
+                    connected_clients.clone().count() > 1 && connected_clients.all(|c| c.ready);
                 if start_lobby {
-                    self.scene = Scene::Gameplay;
-                    let start_gameplay = ServerMessages::StartGameplay;
-                    let start_gameplay = serialize(&start_gameplay).unwrap();
-                    self.server
-                        .broadcast_message(Channel::Reliable.id(), start_gameplay);
-                }
-                if self.lobby_updated {
-                    let lobby_update = ServerMessages::UpdateLobby(self.lobby_info.clone());
-                    let lobby_update = serialize(&lobby_update).unwrap();
-                    self.server
-                        .broadcast_message(Channel::Reliable.id(), lobby_update);
+                    self.start_match();
                 }
             }
             Scene::Gameplay => {
-                self.update_gameplay();
+                let tick_duration = TickRate(self.config.tick_rate).tick_duration();
+                self.accumulator += frame_duration;
+                let mut ticks = 0;
+                // The accumulator is cleared when the match ends
+                while self.accumulator >= tick_duration {
+                    if ticks == MAX_TICKS_PER_UPDATE {
+                        self.accumulator = Duration::ZERO;
+                        break;
+                    }
+                    self.accumulator -= tick_duration;
+                    self.update_gameplay();
+                    ticks += 1;
+                }
+            }
+        }
+
+        // Clients look for games while the server waits in the lobby too
+        if let Some(port) = self.discovery.as_ref().map(|(_, port)| *port) {
+            let announcement = self.announcement(port);
+            if let Some((responder, _)) = self.discovery.as_mut() {
+                responder.update(&announcement);
             }
         }
 
         self.server.send_packets().unwrap();
     }
 
+    // The gameplay values can be changed in the configuration window.
+    fn check_gameplay_config(&mut self) {
+        let gameplay_config = self
+            .world
+            .run(|gameplay_config: UniqueView<GameplayConfig>| gameplay_config.clone())
+            .unwrap();
+        if gameplay_config == self.gameplay_config {
+            return;
+        }
+        // New clients get the values in the server info
+        self.broadcast(&ServerMessages::UpdateGameplayConfig(
+            gameplay_config.clone(),
+        ));
+        self.gameplay_config = gameplay_config;
+    }
+
     fn update_gameplay(&mut self) {
+        let tick = self
+            .world
+            .run(|mut tick: UniqueViewMut<ServerTick>| {
+                tick.0 += 1;
+                tick.0
+            })
+            .unwrap();
+
         // Game logic
+        self.world.run(consume_player_inputs).unwrap();
         self.world.run(update_players_cooldown).unwrap();
         self.world.run(update_animations).unwrap();
         self.world.run(update_players).unwrap();
         self.world.run(update_projectiles).unwrap();
-        self.world.run(cast_fireball_player).unwrap();
+        let casters = self.world.run(cast_fireball_player).unwrap();
+        self.world
+            .run(|mut players_stats: UniqueViewMut<PlayersStats>| {
+                for player_id in casters {
+                    players_stats.get_mut(player_id).shots_fired += 1;
+                }
+            })
+            .unwrap();
         self.world.run(sync_physics).unwrap();
+        self.world.run(record_physics_history).unwrap();
 
         // Clear dead entities
+        let deaths = self.world.run(player_deaths).unwrap();
         self.world.run(remove_zero_health).unwrap();
         self.world.run(remove_dead).unwrap();
         self.world.run(destroy_physics_entities).unwrap();
 
-        let should_check_win = self
+        let round_running = self
             .world
-            .run(|info: UniqueView<GameplayInfo>| {
-                !info.respawn_players && self.lobby_info.clients.len() > 1
-            })
+            .run(|info: UniqueView<GameplayInfo>| !info.respawn_players)
             .unwrap();
+        let tick_duration = TickRate(self.config.tick_rate).tick_duration();
+        if round_running {
+            self.score_deaths(deaths);
+            self.update_respawns(tick_duration);
+        }
 
-        if should_check_win && self.world.run(check_win_condition).unwrap() {
+        self.match_time += tick_duration;
+        let should_check_win = round_running && self.lobby_info.clients.len() > 1;
+        if should_check_win && self.round_over(tick_duration) {
+            self.rounds += 1;
+            self.respawns.clear();
             self.world.run(cleanup_world).unwrap();
             self.world
                 .run(|mut info: UniqueViewMut<GameplayInfo>| {
@@ -228,6 +461,23 @@ impl Game {
                     info.respawn_players = true;
                 })
                 .unwrap();
+            // The world is empty until the next round, a good time to change the level
+            let next_level = self.next_level();
+            self.change_level(next_level);
+            self.send_stats();
+        }
+
+        let match_over = self
+            .world
+            .run(|players_score: UniqueView<PlayersScore>| {
+                self.config
+                    .rules
+                    .is_over(&players_score, self.rounds, self.match_time)
+            })
+            .unwrap();
+        if match_over {
+            self.end_match(tick);
+            return;
         }
 
         let respawn = self
@@ -235,40 +485,797 @@ impl Game {
             .run_with_data(respawn_players, self.lobby_info.clients.len())
             .unwrap();
         if respawn {
-            for &client_id in self.lobby_info.clients.keys() {
-                self.world.run_with_data(create_player, client_id).unwrap();
+            self.respawns.clear();
+            // Disconnected players spawn when they reconnect in the next rounds
+            let connected_players: Vec<PlayerId> = self
+                .lobby_info
+                .clients
+                .iter()
+                .filter(|(_, client_info)| client_info.connected)
+                .map(|(player_id, _)| *player_id)
+                .collect();
+            for player_id in connected_players {
+                self.spawn_player(player_id);
             }
         }
 
-        let server_frame = ServerFrame::from_world(&self.world);
-        let server_frame = serialize(&server_frame).unwrap();
-        self.server
-            .broadcast_message(Channel::Unreliable.id(), server_frame);
+        if tick % self.config.send_interval() == 0 {
+            self.send_server_frame(tick);
+        }
 
         // Send score update to clients
-        {
+        let score_message = {
             let mut score = self.world.borrow::<UniqueViewMut<PlayersScore>>().unwrap();
             if score.updated {
-                let score_message = ServerMessages::UpdateScore((*score).clone());
-                let score_message = serialize(&score_message).unwrap();
-                self.server
-                    .broadcast_message(Channel::Reliable.id(), score_message);
                 score.updated = false;
+                Some(ServerMessages::UpdateScore((*score).clone()))
+            } else {
+                None
+            }
+        };
+        if let Some(score_message) = score_message {
+            self.broadcast(&score_message);
+        }
+
+        if tick % self.config.stats_interval() == 0 {
+            self.send_stats();
+        }
+    }
+
+    // Sends the stats when they changed since the last update.
+    fn send_stats(&mut self) {
+        let stats_message = {
+            let mut stats = self.world.borrow::<UniqueViewMut<PlayersStats>>().unwrap();
+            if stats.updated {
+                stats.updated = false;
+                Some(ServerMessages::UpdateStats((*stats).clone()))
+            } else {
+                None
+            }
+        };
+        if let Some(stats_message) = stats_message {
+            self.broadcast(&stats_message);
+        }
+    }
+
+    fn score_deaths(&mut self, deaths: Vec<Death>) {
+        {
+            let mut score = self.world.borrow::<UniqueViewMut<PlayersScore>>().unwrap();
+            let mut stats = self.world.borrow::<UniqueViewMut<PlayersStats>>().unwrap();
+            for death in deaths.iter() {
+                self.mode.player_died(*death, &self.lobby_info, &mut score);
+                if let Some(delay) = self.mode.respawn_delay() {
+                    self.respawns.insert(death.victim, delay);
+                }
+
+                stats.get_mut(death.victim).deaths += 1;
+                // Killing a teammate with friendly fire is not credited
+                let killer = death.killer.filter(|killer| {
+                    let team = self.lobby_info.team(*killer);
+                    team.is_none() || team != self.lobby_info.team(death.victim)
+                });
+                if let Some(killer) = killer {
+                    stats.get_mut(killer).kills += 1;
+                }
+            }
+        }
+        for death in deaths {
+            self.broadcast(&ServerMessages::Kill {
+                victim: death.victim,
+                killer: death.killer,
+            });
+        }
+    }
+
+    // Spawns the dead players whose delay is over, disconnected players spawn when they are back.
+    fn update_respawns(&mut self, tick_duration: Duration) {
+        let mut spawned = vec![];
+        for (player_id, time_left) in self.respawns.iter_mut() {
+            *time_left = time_left.saturating_sub(tick_duration);
+            let connected = self
+                .lobby_info
+                .clients
+                .get(player_id)
+                .map_or(false, |client_info| client_info.connected);
+            if *time_left == Duration::ZERO && connected {
+                spawned.push(*player_id);
             }
         }
+        for player_id in spawned {
+            self.respawns.remove(&player_id);
+            self.spawn_player(player_id);
+        }
+    }
+
+    fn round_over(&mut self, tick_duration: Duration) -> bool {
+        let alive = self.world.run(alive_players).unwrap();
+        let hill = self.world.borrow::<UniqueView<LevelHill>>().unwrap().0;
+        let mut score = self.world.borrow::<UniqueViewMut<PlayersScore>>().unwrap();
+        let round = RoundState {
+            alive: &alive,
+            hill,
+            tick_duration,
+        };
+        self.mode.update(&round, &mut score)
+    }
+
+    fn start_match(&mut self) {
+        info!("All clients are ready, starting gameplay");
+        self.scene = Scene::Gameplay;
+        self.rounds = 0;
+        self.match_time = Duration::ZERO;
+        self.respawns.clear();
+
+        let mode = self.voted_mode();
+        info!("Playing {}", mode.title());
+        self.mode = new_game_mode(mode);
+        self.lobby_info.mode = mode;
+        self.assign_teams();
+        self.broadcast(&ServerMessages::UpdateLobby(self.lobby_info.clone()));
+        let team_score = if mode.has_teams() {
+            Team::ALL.iter().map(|team| (*team, 0)).collect()
+        } else {
+            HashMap::new()
+        };
+        self.world
+            .run(
+                |mut players_score: UniqueViewMut<PlayersScore>,
+                 mut players_stats: UniqueViewMut<PlayersStats>| {
+                    players_score.team_score = team_score;
+                    players_score.updated = true;
+                    players_stats.stats.clear();
+                    players_stats.updated = true;
+                },
+            )
+            .unwrap();
+        // Players spawn when the countdown of the first round ends
+        self.world
+            .run(|mut info: UniqueViewMut<GameplayInfo>| {
+                info.respawn_players_timer.reset();
+                info.respawn_players = true;
+            })
+            .unwrap();
+        self.broadcast(&ServerMessages::StartGameplay);
+    }
+
+    // Sends the final standings and takes the players back to the lobby,
+    // they have to ready up again for the next match.
+    fn end_match(&mut self, tick: u64) {
+        info!(
+            "Match over after {} rounds and {:.0?}",
+            self.rounds, self.match_time
+        );
+        let (players_score, players_stats) = self
+            .world
+            .run(
+                |players_score: UniqueView<PlayersScore>,
+                 players_stats: UniqueView<PlayersStats>| {
+                    (players_score.clone(), players_stats.clone())
+                },
+            )
+            .unwrap();
+        let mut standings: Vec<Standing> = players_score
+            .score
+            .iter()
+            .map(|(&player_id, &score)| Standing {
+                player_id,
+                name: self.lobby_info.player_name(player_id),
+                team: self.lobby_info.team(player_id),
+                score,
+                stats: players_stats.get(player_id),
+            })
+            .collect();
+        standings.sort_by(|a, b| b.score.cmp(&a.score).then(a.player_id.cmp(&b.player_id)));
+        let mut team_scores: Vec<(Team, u8)> = players_score.team_score.into_iter().collect();
+        team_scores.sort_by(|a, b| b.1.cmp(&a.1));
+        self.broadcast(&ServerMessages::MatchOver {
+            standings,
+            team_scores,
+        });
+
+        // Clients see the empty level behind the results
+        self.world.run(cleanup_world).unwrap();
+        self.send_server_frame(tick);
+
+        let (score, stats) = self
+            .world
+            .run(
+                |mut players_score: UniqueViewMut<PlayersScore>,
+                 mut players_stats: UniqueViewMut<PlayersStats>,
+                 mut info: UniqueViewMut<GameplayInfo>| {
+                    for score in players_score.score.values_mut() {
+                        *score = 0;
+                    }
+                    players_score.team_score.clear();
+                    players_score.updated = false;
+                    players_stats.stats.clear();
+                    players_stats.updated = false;
+                    info.respawn_players = false;
+                    (players_score.clone(), players_stats.clone())
+                },
+            )
+            .unwrap();
+        self.broadcast(&ServerMessages::UpdateScore(score));
+        self.broadcast(&ServerMessages::UpdateStats(stats));
+
+        for client_info in self.lobby_info.clients.values_mut() {
+            client_info.ready = false;
+        }
+        self.broadcast(&ServerMessages::UpdateLobby(self.lobby_info.clone()));
+
+        self.scene = Scene::Lobby;
+        self.respawns.clear();
+        self.accumulator = Duration::ZERO;
+        self.rounds = 0;
+        self.match_time = Duration::ZERO;
+    }
+
+    // The mode with most votes of the players, random in a tie.
+    fn voted_mode(&self) -> GameModeKind {
+        let mut votes: HashMap<GameModeKind, usize> = HashMap::new();
+        for client_info in self.lobby_info.clients.values() {
+            if let Some(mode) = client_info.mode_vote {
+                *votes.entry(mode).or_default() += 1;
+            }
+        }
+        let most_votes = match votes.values().max() {
+            Some(most_votes) => *most_votes,
+            None => return self.config.mode,
+        };
+        let mut tied: Vec<GameModeKind> = votes
+            .into_iter()
+            .filter(|(_, count)| *count == most_votes)
+            .map(|(mode, _)| mode)
+            .collect();
+        tied.sort();
+        tied[random_index(tied.len())]
+    }
+
+    // Players keep the team they picked, the others are balanced between the teams.
+    // Teams are cleared when the mode has none.
+    fn assign_teams(&mut self) {
+        let teams = self.lobby_info.mode.has_teams();
+        for client_info in self.lobby_info.clients.values_mut() {
+            client_info.team = client_info.team_pick.filter(|_| teams);
+        }
+        if !teams {
+            return;
+        }
+        // Picks are kept up to an even split, the players who joined last leave a full team
+        let max_team_size = (self.lobby_info.clients.len() + Team::ALL.len() - 1) / Team::ALL.len();
+        for team in Team::ALL.iter() {
+            let mut members: Vec<PlayerId> = self
+                .lobby_info
+                .clients
+                .iter()
+                .filter(|(_, client_info)| client_info.team == Some(*team))
+                .map(|(player_id, _)| *player_id)
+                .collect();
+            members.sort();
+            for player_id in members.into_iter().skip(max_team_size) {
+                if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                    client_info.team = None;
+                }
+            }
+        }
+        let mut unassigned: Vec<PlayerId> = self
+            .lobby_info
+            .clients
+            .iter()
+            .filter(|(_, client_info)| client_info.team.is_none())
+            .map(|(player_id, _)| *player_id)
+            .collect();
+        unassigned.sort();
+        for player_id in unassigned {
+            let team = self.smallest_team();
+            if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                client_info.team = Some(team);
+            }
+        }
+    }
+
+    fn smallest_team(&self) -> Team {
+        let team_size = |team: &Team| {
+            self.lobby_info
+                .clients
+                .values()
+                .filter(|client_info| client_info.team == Some(*team))
+                .count()
+        };
+        Team::ALL.iter().copied().min_by_key(team_size).unwrap()
+    }
+
+    // Team of a player joining a running match.
+    fn late_player_team(&self) -> Option<Team> {
+        if self.lobby_info.mode.has_teams() {
+            Some(self.smallest_team())
+        } else {
+            None
+        }
+    }
+
+    fn spawn_player(&mut self, player_id: PlayerId) {
+        let team = self
+            .lobby_info
+            .clients
+            .get(&player_id)
+            .and_then(|client_info| client_info.team);
+        self.world
+            .run_with_data(create_player, (player_id, team))
+            .unwrap();
+    }
+
+    fn next_level(&self) -> String {
+        let current = self
+            .level_pool
+            .iter()
+            .position(|level| *level == self.config.level);
+        let sequential = || match current {
+            Some(index) => self.level_pool[(index + 1) % self.level_pool.len()].clone(),
+            None => self.level_pool[0].clone(),
+        };
+        match self.config.level_rotation {
+            LevelRotation::Fixed => self.config.level.clone(),
+            LevelRotation::Sequential => sequential(),
+            LevelRotation::Random => {
+                // A different level each round when there are more
+                let levels: Vec<&String> = self
+                    .level_pool
+                    .iter()
+                    .filter(|level| self.level_pool.len() == 1 || **level != self.config.level)
+                    .collect();
+                levels[random_index(levels.len())].clone()
+            }
+            LevelRotation::Vote => {
+                let mut votes: HashMap<&String, usize> = HashMap::new();
+                for client_info in self.lobby_info.clients.values() {
+                    if let Some(level) = client_info.level_vote.as_ref() {
+                        *votes.entry(level).or_default() += 1;
+                    }
+                }
+                let most_votes = match votes.values().max() {
+                    Some(most_votes) => *most_votes,
+                    None => return sequential(),
+                };
+                let mut tied: Vec<&String> = votes
+                    .into_iter()
+                    .filter(|(_, count)| *count == most_votes)
+                    .map(|(level, _)| level)
+                    .collect();
+                tied.sort();
+                tied[random_index(tied.len())].clone()
+            }
+        }
+    }
+
+    // Rebuilds the collisions of the level and tells the clients to render it.
+    fn change_level(&mut self, level: String) {
+        // Players vote again for the next round
+        let mut votes_cleared = false;
+        for client_info in self.lobby_info.clients.values_mut() {
+            votes_cleared |= client_info.level_vote.take().is_some();
+        }
+        if votes_cleared {
+            self.broadcast(&ServerMessages::UpdateLobby(self.lobby_info.clone()));
+        }
+
+        if level == self.config.level {
+            return;
+        }
+        info!("Changing level to {}", level);
+        // Levels of the pool are checked when the server starts
+        replace_level_collisions(&self.world, &level).expect("Level of the pool not found.");
+        self.config.level = level.clone();
+        self.broadcast(&ServerMessages::ChangeLevel(level));
+    }
+
+    // Sends the message to the clients that were accepted, the others are still in the handshake.
+    fn broadcast(&mut self, message: &ServerMessages) {
+        let message = serialize(message).unwrap();
+        for client_id in self.players.keys() {
+            if let Err(e) =
+                self.server
+                    .send_message(client_id, Channel::Reliable.id(), message.clone())
+            {
+                error!("Error sending message: {}", e);
+            }
+        }
+    }
+
+    fn send_server_frame(&mut self, tick: u64) {
+        let server_frame = ServerFrame::from_world(tick, &self.world, &self.network_registry);
+        for client_id in self.players.keys() {
+            // Clients without an acknowledged baseline still in the history receive the full frame.
+            let baseline = self
+                .client_acks
+                .get(client_id)
+                .and_then(|frame| self.frame_history.get(*frame));
+            let message = match baseline {
+                Some(baseline) => {
+                    let delta = server_frame.delta(baseline, &self.network_registry);
+                    delta.to_bytes()
+                }
+                None => server_frame.to_bytes(),
+            };
+            if let Err(e) = self
+                .server
+                .send_message(client_id, Channel::Unreliable.id(), message)
+            {
+                error!("Error sending server frame: {}", e);
+            }
+        }
+        self.frame_history.insert(server_frame);
+    }
+
+    // Spawns a player that joined while a round is running, when the server allows it.
+    // Otherwise the client spectates until the players respawn in the next round.
+    fn spawn_late_player(&mut self, player_id: PlayerId) {
+        let round_running = self
+            .world
+            .run(|info: UniqueView<GameplayInfo>| !info.respawn_players)
+            .unwrap();
+        if self.config.late_join_spawn && round_running {
+            info!("Spawning late player {}", player_id);
+            self.spawn_player(player_id);
+        } else if let Some(delay) = self.mode.respawn_delay() {
+            // Like a dead player in the modes with respawns
+            self.respawns.insert(player_id, delay);
+        }
+    }
+
+    fn announcement(&self, port: u16) -> ServerAnnouncement {
+        ServerAnnouncement {
+            protocol_version: PROTOCOL_VERSION,
+            name: self.config.name.clone(),
+            port,
+            level: self.config.level.clone(),
+            mode: self.mode.kind().title().to_string(),
+            players: self.players.len() as u32,
+            max_players: self.config.max_clients as u32,
+        }
+    }
+
+    fn read_handshake(&mut self, client_id: &SocketAddr) {
+        let message = match self
+            .server
+            .receive_message(client_id, Channel::Reliable.id())
+        {
+            Some(message) => message,
+            None => return,
+        };
+        let result = match decode_message::<Handshake>(&message) {
+            Ok(handshake) => self.check_handshake(&handshake),
+            Err(e) => Err(format!("invalid handshake: {}", e)),
+        };
+        let response = match &result {
+            Ok(()) => HandshakeResponse::Accepted,
+            Err(reason) => HandshakeResponse::Rejected(reason.clone()),
+        };
+        let response = serialize(&response).unwrap();
+        if let Err(e) = self
+            .server
+            .send_message(client_id, Channel::Reliable.id(), response)
+        {
+            error!("Error sending handshake response: {}", e);
+        }
+
+        match result {
+            Ok(()) => self.accept_client(*client_id),
+            Err(reason) => {
+                info!("Rejected client {}: {}", client_id, reason);
+                self.closing_clients.insert(*client_id, CLOSE_DELAY);
+            }
+        }
+    }
+
+    fn check_handshake(&self, handshake: &Handshake) -> Result<(), String> {
+        if handshake.protocol_version != PROTOCOL_VERSION {
+            return Err(format!(
+                "server uses protocol version {} and the client version {}, \
+                 both must run the same build",
+                PROTOCOL_VERSION, handshake.protocol_version
+            ));
+        }
+        if handshake.content_hash != self.content_hash {
+            return Err("levels or gameplay values differ from the server".to_string());
+        }
+        Ok(())
+    }
+
+    fn accept_client(&mut self, client_id: SocketAddr) {
+        let player_id = PlayerId(self.next_player_id);
+        self.next_player_id += 1;
+        self.players.insert(client_id, player_id);
+        self.sessions.insert(new_session_token(), player_id);
+        info!("Client {} joined as player {}", client_id, player_id);
+
+        // Clients joining a running match play from the next round on
+        let playing = self.scene == Scene::Gameplay;
+        let client_info = ClientInfo {
+            ready: playing,
+            team: self.late_player_team().filter(|_| playing),
+            ..ClientInfo::new(default_name(player_id))
+        };
+        self.lobby_info.clients.insert(player_id, client_info);
+        self.lobby_updated = true;
+
+        self.world
+            .run(|mut players_score: UniqueViewMut<PlayersScore>| {
+                players_score.score.insert(player_id, 0);
+                players_score.updated = true;
+            })
+            .unwrap();
+
+        self.send_server_info(&client_id, player_id);
+
+        if self.scene == Scene::Gameplay {
+            self.spawn_late_player(player_id);
+        }
+    }
+
+    fn send_server_info(&mut self, client_id: &SocketAddr, player_id: PlayerId) {
+        let session_token = self
+            .sessions
+            .iter()
+            .find(|(_, session_player_id)| **session_player_id == player_id)
+            .map(|(token, _)| *token)
+            .unwrap();
+        let (score, stats) = self
+            .world
+            .run(
+                |players_score: UniqueView<PlayersScore>,
+                 players_stats: UniqueView<PlayersStats>| {
+                    (players_score.clone(), players_stats.clone())
+                },
+            )
+            .unwrap();
+        let server_info = ServerMessages::ServerInfo(ServerInfo {
+            player_id,
+            session_token,
+            tick_rate: TickRate(self.config.tick_rate),
+            scene: self.scene,
+            level: self.config.level.clone(),
+            levels: self.level_pool.clone(),
+            level_rotation: self.config.level_rotation,
+            rules: self.config.rules,
+            score,
+            stats,
+            gameplay_config: self.gameplay_config.clone(),
+        });
+        let server_info = serialize(&server_info).unwrap();
+        if let Err(e) = self
+            .server
+            .send_message(client_id, Channel::Reliable.id(), server_info)
+        {
+            error!("Error sending server info: {}", e);
+        }
+    }
+
+    // Keeps the lobby slot and score of a disconnected player, so they can be
+    // restored when the client reconnects with the session token.
+    fn hold_session(&mut self, player_id: PlayerId) {
+        if self.config.reconnect_timeout == 0 {
+            self.remove_session(player_id);
+            return;
+        }
+
+        if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+            client_info.connected = false;
+            self.lobby_updated = true;
+        }
+        if self.config.keep_disconnected_players {
+            // The player stands still until the client is back
+            self.world
+                .run(
+                    |player_mapping: UniqueView<PlayerMapping>,
+                     mut input_queues: ViewMut<PlayerInputQueue>,
+                     mut inputs: ViewMut<PlayerInput>| {
+                        if let Some(&entity_id) = player_mapping.get(&player_id) {
+                            if let Ok(mut input_queue) = (&mut input_queues).get(entity_id) {
+                                input_queue.0.clear();
+                            }
+                            if let Ok(mut input) = (&mut inputs).get(entity_id) {
+                                *input = PlayerInput::default();
+                            }
+                        }
+                    },
+                )
+                .unwrap();
+        } else {
+            self.world.run_with_data(remove_player, player_id).unwrap();
+        }
+        let timeout = Duration::from_secs(self.config.reconnect_timeout as u64);
+        self.held_sessions.insert(player_id, timeout);
+    }
+
+    fn remove_session(&mut self, player_id: PlayerId) {
+        self.held_sessions.remove(&player_id);
+        self.respawns.remove(&player_id);
+        self.sessions
+            .retain(|_, session_player_id| *session_player_id != player_id);
+        self.lobby_info.clients.remove(&player_id);
+        self.lobby_updated = true;
+
+        self.world.run_with_data(remove_player, player_id).unwrap();
+        self.world
+            .run(
+                |mut players_score: UniqueViewMut<PlayersScore>,
+                 mut players_stats: UniqueViewMut<PlayersStats>| {
+                    players_score.score.remove(&player_id);
+                    players_score.updated = true;
+                    players_stats.stats.remove(&player_id);
+                    players_stats.updated = true;
+                },
+            )
+            .unwrap();
+    }
+
+    // Moves the connection to the player of a held session, the player
+    // created for the connection is discarded. Returns the player of the connection.
+    fn resume_session(
+        &mut self,
+        client_id: &SocketAddr,
+        player_id: PlayerId,
+        session_token: SessionToken,
+    ) -> PlayerId {
+        let held_player_id = match self.sessions.get(&session_token) {
+            Some(held_player_id) if self.held_sessions.contains_key(held_player_id) => {
+                *held_player_id
+            }
+            _ => return player_id,
+        };
+
+        info!(
+            "Client {} resumed the session of player {}",
+            client_id, held_player_id
+        );
+        self.remove_session(player_id);
+        self.held_sessions.remove(&held_player_id);
+        self.players.insert(*client_id, held_player_id);
+        if let Some(client_info) = self.lobby_info.clients.get_mut(&held_player_id) {
+            client_info.connected = true;
+        }
+        self.send_server_info(client_id, held_player_id);
+
+        // The player was removed when the connection was lost, rounds with respawns
+        // may never end so it spawns again like a dead player
+        let spawned = self
+            .world
+            .run(|player_mapping: UniqueView<PlayerMapping>| {
+                player_mapping.contains_key(&held_player_id)
+            })
+            .unwrap();
+        if self.scene == Scene::Gameplay && !spawned {
+            if let Some(delay) = self.mode.respawn_delay() {
+                self.respawns.entry(held_player_id).or_insert(delay);
+            }
+        }
+
+        held_player_id
+    }
+
+    // Closes the connection of a misbehaving client, its session can not be resumed.
+    fn kick(&mut self, client_id: &SocketAddr, reason: &str) {
+        info!("Kicking client {}: {}", client_id, reason);
+        let kicked = ServerMessages::Kicked {
+            reason: reason.to_string(),
+        };
+        let kicked = serialize(&kicked).unwrap();
+        if let Err(e) = self
+            .server
+            .send_message(client_id, Channel::Reliable.id(), kicked)
+        {
+            error!("Error sending kick reason: {}", e);
+        }
+        self.remove_client(client_id);
+        self.closing_clients.insert(*client_id, CLOSE_DELAY);
+    }
+
+    // The client left on its own, its session is not held.
+    fn leave(&mut self, client_id: &SocketAddr) {
+        info!("Client {} left", client_id);
+        self.remove_client(client_id);
+        self.server.disconnect(client_id);
+    }
+
+    fn remove_client(&mut self, client_id: &SocketAddr) {
+        self.client_acks.remove(client_id);
+        self.client_guards.remove(client_id);
+        if let Some(player_id) = self.players.remove(client_id) {
+            self.remove_session(player_id);
+        }
+    }
+
+    /// Tells the clients the server is closing and disconnects them.
+    pub fn shutdown(&mut self) {
+        info!("Shutting down the server");
+        self.broadcast(&ServerMessages::Shutdown);
+        if let Err(e) = self.server.send_packets() {
+            error!("{}", e);
+        }
+        for client_id in self.server.clients_id() {
+            self.server.disconnect(&client_id);
+        }
     }
 
     fn handle_client_action(&mut self, action: ClientAction, client_id: &SocketAddr) {
+        // The player changes when the client resumes a session
+        let player_id = match self.players.get(client_id) {
+            Some(player_id) => *player_id,
+            None => return,
+        };
         match action {
+            ClientAction::Join(JoinRequest {
+                name,
+                session_token,
+            }) => {
+                let player_id = match session_token {
+                    Some(session_token) => self.resume_session(client_id, player_id, session_token),
+                    None => player_id,
+                };
+                if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                    client_info.name =
+                        sanitize_name(&name).unwrap_or_else(|| default_name(player_id));
+                    self.lobby_updated = true;
+                }
+            }
             ClientAction::LobbyReady => {
-                let client_info = self.lobby_info.clients.get_mut(client_id).unwrap();
-                client_info.ready = !client_info.ready;
-                self.lobby_updated = true;
+                if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                    client_info.ready = !client_info.ready;
+                    self.lobby_updated = true;
+                }
             }
+            ClientAction::VoteLevel(level) => {
+                let voting = self.config.level_rotation == LevelRotation::Vote;
+                if !voting || !self.level_pool.contains(&level) {
+                    return;
+                }
+                if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                    client_info.level_vote = Some(level);
+                    self.lobby_updated = true;
+                }
+            }
+            ClientAction::VoteMode(mode) => {
+                if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                    client_info.mode_vote = Some(mode);
+                    self.lobby_updated = true;
+                }
+            }
+            ClientAction::PickTeam(team) => {
+                if let Some(client_info) = self.lobby_info.clients.get_mut(&player_id) {
+                    client_info.team_pick = team;
+                    self.lobby_updated = true;
+                }
+            }
+            ClientAction::Leave => self.leave(client_id),
         }
     }
 }
 
+// The token is all a client needs to take over a held player.
+fn new_session_token() -> SessionToken {
+    SessionToken(random_u64())
+}
+
+fn default_name(player_id: PlayerId) -> String {
+    format!("Player {}", player_id.0)
+}
+
+// Removes control characters and limits the length, returns None when nothing is left.
+fn sanitize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    let name = name.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
 fn update_animations(mut animations_controller: ViewMut<AnimationController>) {
     for mut animation_controller in (&mut animations_controller).iter() {
         animation_controller.update();
@@ -283,34 +1290,58 @@ fn update_projectiles(mut all_storages: AllStoragesViewMut) {
         let mut health = all_storages.borrow::<ViewMut<Health>>().unwrap();
         let players = all_storages.borrow::<View<Player>>().unwrap();
         let mut physics = all_storages.borrow::<UniqueViewMut<Physics>>().unwrap();
+        let tick = all_storages.borrow::<UniqueView<ServerTick>>().unwrap().0;
+        let tick_rate = *all_storages.borrow::<UniqueView<TickRate>>().unwrap();
+        let friendly_fire = all_storages.borrow::<UniqueView<FriendlyFire>>().unwrap().0;
+        let mut players_stats = all_storages
+            .borrow::<UniqueViewMut<PlayersStats>>()
+            .unwrap();
 
         for (entity_id, mut projectile) in (&mut projectiles).iter().with_id() {
+            let (caster, caster_team) = match (&players).get(projectile.owner) {
+                Ok(owner) => (Some(owner.player_id), owner.team),
+                Err(_) => (None, None),
+            };
+
             projectile.duration = projectile
                 .duration
-                .checked_sub(Duration::from_micros(16666))
+                .checked_sub(tick_rate.tick_duration())
                 .unwrap_or_else(|| Duration::from_micros(0));
             if projectile.duration.as_micros() == 0 {
                 remove.push(entity_id);
             }
 
             // Apply gravity to projectiles
-            projectile.speed.y += 1000. * get_frame_time();
+            projectile.speed.y += 1000. * tick_rate.frame_time();
 
-            if physics.move_h(entity_id, projectile.speed.x * get_frame_time())
-                || physics.move_v(entity_id, projectile.speed.y * get_frame_time())
+            if physics.move_h(entity_id, projectile.speed.x * tick_rate.frame_time())
+                || physics.move_v(entity_id, projectile.speed.y * tick_rate.frame_time())
             {
                 deads.add_component_unchecked(entity_id, Dead);
                 return;
             }
 
-            for (player_id, (player, mut health)) in (&players, &mut health).iter().with_id() {
+            for (player_id, (target, mut health)) in (&players, &mut health).iter().with_id() {
                 if player_id == projectile.owner {
                     continue;
                 }
+                // Without friendly fire the fireball goes through teammates
+                let teammate = caster_team.is_some() && target.team == caster_team;
+                if teammate && !friendly_fire {
+                    continue;
+                }
 
-                if physics.overlaps_actor(entity_id, player_id) {
-                    health.take_damage(1, Some(player.client_id));
+                // Check against where the target was on the caster screen
+                let rewind_tick = tick.saturating_sub(projectile.rewind_ticks);
+                if physics.overlaps_actor_at(entity_id, player_id, rewind_tick) {
+                    let health_before = health.current;
+                    health.take_damage(1, caster);
                     deads.add_component_unchecked(entity_id, Dead);
+                    if let Some(caster) = caster {
+                        let stats = players_stats.get_mut(caster);
+                        stats.hits += 1;
+                        stats.damage_dealt += (health_before - health.current) as u32;
+                    }
                 }
             }
         }
@@ -327,13 +1358,17 @@ fn cast_fireball_player(
     mut transforms: ViewMut<Transform>,
     mut projectiles: ViewMut<Projectile>,
     mut physics: UniqueViewMut<Physics>,
-) {
+    gameplay: UniqueView<GameplayConfig>,
+    tick: UniqueView<ServerTick>,
+    tick_rate: UniqueView<TickRate>,
+) -> Vec<PlayerId> {
     let mut created_projectiles = vec![];
+    let mut casters = vec![];
     for (player_id, (mut player, input, transform)) in
         (&mut players, &inputs, &transforms).iter().with_id()
     {
         if input.fire && player.fireball_cooldown.is_finished() {
-            player.fireball_charge += get_frame_time();
+            player.fireball_charge += tick_rate.frame_time();
             player.fireball_charge = player
                 .fireball_charge
                 .clamp(0.0, player.fireball_max_charge);
@@ -341,7 +1376,7 @@ fn cast_fireball_player(
             // Fireball cooldown
             if !player.fireball_cooldown.is_finished() {
                 player.fireball_charge = 0.;
-                return;
+                continue;
             }
             let pos = transform.position + vec2(4., 6.);
 
@@ -349,7 +1384,13 @@ fn cast_fireball_player(
             physics.add_actor(entity_id, pos, 4, 4);
 
             let speed = input.direction * (200. * (1. + player.fireball_charge * 3.));
-            let projectile = Projectile::new(ProjectileType::Fireball, speed, player_id);
+            let mut projectile = Projectile::new(ProjectileType::Fireball, speed, player_id);
+            if input.view_tick != 0 {
+                projectile.rewind_ticks = tick
+                    .0
+                    .saturating_sub(input.view_tick)
+                    .min(gameplay.max_rewind_ticks);
+            }
             let rotation = input.direction.angle_between(Vec2::X);
 
             let projectile_transform = Transform::new(pos, rotation);
@@ -357,6 +1398,7 @@ fn cast_fireball_player(
 
             player.fireball_cooldown.reset();
             player.fireball_charge = 0.;
+            casters.push(player.player_id);
         }
     }
 
@@ -367,6 +1409,7 @@ fn cast_fireball_player(
             components.clone(),
         );
     }
+    casters
 }
 
 fn update_players(
@@ -375,60 +1418,19 @@ fn update_players(
     mut animations: ViewMut<AnimationController>,
     mut physics: UniqueViewMut<Physics>,
     gameplay: UniqueView<GameplayConfig>,
+    tick_rate: UniqueView<TickRate>,
 ) {
     for (entity_id, (mut player, input, mut animation)) in
         (&mut players, &inputs, &mut animations).iter().with_id()
     {
-        let x = (input.right as i8 - input.left as i8) as f32;
-        let y = (input.down as i8 - input.up as i8) as f32;
-        let movement_direction = vec2(x, y);
-        player.direction = if input.direction.length() != 0.0 {
-            input.direction.normalize()
-        } else {
-            input.direction
-        };
-
-        if input.dash && player.dash_cooldown.is_finished() {
-            player.dash_cooldown.reset();
-            player.current_dash_duration = player.dash_duration;
-
-            // If there is no player input use player facing direction
-            let dash_direction = if movement_direction.length() != 0.0 {
-                movement_direction.normalize()
-            } else {
-                vec2(input.direction.x.signum(), 0.)
-            };
-            player.speed = dash_direction * gameplay.dash_speed;
-        }
-
-        let pos = physics.actor_pos(entity_id);
-        let on_ground = physics.collide_check(entity_id, pos + vec2(0., 1.));
-
-        if player.current_dash_duration > 0.0 {
-            player.current_dash_duration -= get_frame_time();
-            if player.current_dash_duration <= 0.0 {
-                player.speed = player.speed.normalize() * gameplay.walk_speed;
-            }
-        } else {
-            if !on_ground {
-                player.speed.y += gameplay.player_gravity * get_frame_time();
-            } else {
-                player.speed.y = gameplay.player_gravity * get_frame_time();
-            }
-
-            player.speed.x = movement_direction.x * gameplay.walk_speed;
-            if input.jump && on_ground {
-                player.speed.y = -gameplay.jump_speed;
-            }
-        }
-
-        if physics.move_h(entity_id, player.speed.x * get_frame_time()) {
-            player.current_dash_duration = 0.;
-        }
-        if physics.move_v(entity_id, player.speed.y * get_frame_time()) {
-            player.current_dash_duration = 0.;
-            player.speed.y = 0.0;
-        }
+        let on_ground = update_player_movement(
+            entity_id,
+            &mut player,
+            input,
+            &mut physics,
+            &gameplay,
+            tick_rate.frame_time(),
+        );
 
         // Update animation
         if input.right ^ input.left || input.down ^ input.up || !on_ground {
@@ -439,21 +1441,42 @@ fn update_players(
     }
 }
 
-fn update_players_cooldown(mut players: ViewMut<Player>) {
+fn consume_player_inputs(
+    mut players: ViewMut<Player>,
+    mut input_queues: ViewMut<PlayerInputQueue>,
+    mut inputs: ViewMut<PlayerInput>,
+) {
+    let mut consumed_inputs = vec![];
+    for (entity_id, (mut player, mut input_queue)) in
+        (&mut players, &mut input_queues).iter().with_id()
+    {
+        // When no input arrived the last one is simulated again
+        if let Some(input) = input_queue.0.pop_front() {
+            player.input_sequence = input.sequence;
+            consumed_inputs.push((entity_id, input));
+        }
+    }
+
+    for (entity_id, input) in consumed_inputs {
+        inputs.add_component_unchecked(entity_id, input);
+    }
+}
+
+fn update_players_cooldown(mut players: ViewMut<Player>, tick_rate: UniqueView<TickRate>) {
     for mut player in (&mut players).iter() {
-        player.fireball_cooldown.update(get_frame_time());
-        player.dash_cooldown.update(get_frame_time());
+        player.fireball_cooldown.update(tick_rate.frame_time());
     }
 }
 
 fn create_player(
-    client_id: SocketAddr,
+    (player_id, team): (PlayerId, Option<Team>),
     player_respawn_points: UniqueView<PlayerRespawnPoints>,
     mut entities: EntitiesViewMut,
     mut transforms: ViewMut<Transform>,
     mut players: ViewMut<Player>,
     mut health: ViewMut<Health>,
     mut animations: ViewMut<AnimationController>,
+    mut input_queues: ViewMut<PlayerInputQueue>,
     mut player_mapping: UniqueViewMut<PlayerMapping>,
     mut physics: UniqueViewMut<Physics>,
 ) {
@@ -463,15 +1486,18 @@ fn create_player(
         .unwrap()
         .as_micros();
 
-    let mut player_position =
-        player_respawn_points.0[rand as usize % player_respawn_points.0.len()];
+    let positions = player_respawn_points.positions(team);
+    let mut player_position = positions[rand as usize % positions.len()];
     // player_respawn_points.0[rand::rand() as usize % player_respawn_points.0.len()];
 
     player_position.y -= 16.;
 
     physics.add_actor(entity_id, player_position, 8, 12);
 
-    let player = Player::new(client_id);
+    let player = Player {
+        team,
+        ..Player::new(player_id)
+    };
     let transform = Transform::default();
     let animation = AnimationEntity::Player.new_animation_controller();
 
@@ -479,19 +1505,31 @@ fn create_player(
 
     entities.add_component(
         entity_id,
-        (&mut players, &mut transforms, &mut animations, &mut health),
-        (player, transform, animation, player_health),
+        (
+            &mut players,
+            &mut transforms,
+            &mut animations,
+            &mut health,
+            &mut input_queues,
+        ),
+        (
+            player,
+            transform,
+            animation,
+            player_health,
+            PlayerInputQueue::default(),
+        ),
     );
 
-    player_mapping.insert(client_id, entity_id);
+    player_mapping.insert(player_id, entity_id);
 }
 
-fn remove_player(client_id: SocketAddr, mut all_storages: AllStoragesViewMut) {
+fn remove_player(player_id: PlayerId, mut all_storages: AllStoragesViewMut) {
     let player_entity_id = {
         let mut player_mapping = all_storages
             .borrow::<UniqueViewMut<PlayerMapping>>()
             .unwrap();
-        player_mapping.remove(&client_id)
+        player_mapping.remove(&player_id)
     };
     if let Some(entity_id) = player_entity_id {
         all_storages.delete_entity(entity_id);
@@ -536,6 +1574,14 @@ fn sync_physics(
     }
 }
 
+fn record_physics_history(
+    mut physics: UniqueViewMut<Physics>,
+    gameplay: UniqueView<GameplayConfig>,
+    tick: UniqueView<ServerTick>,
+) {
+    physics.record_actors_history(tick.0, gameplay.max_rewind_ticks as usize + 1);
+}
+
 fn destroy_physics_entities(
     mut physics: UniqueViewMut<Physics>,
     mut projectiles: ViewMut<Projectile>,
@@ -545,19 +1591,23 @@ fn destroy_physics_entities(
     }
 }
 
-fn check_win_condition(
-    players: View<Player>,
-    mut players_score: UniqueViewMut<PlayersScore>,
-) -> bool {
-    let win_codition = players.iter().count() <= 1;
-    if win_codition {
-        if let Some(player) = players.iter().next() {
-            let score = players_score.score.entry(player.client_id).or_insert(0);
-            *score += 1;
-            players_score.updated = true;
-        }
-    }
-    win_codition
+fn player_deaths(players: View<Player>, health: View<Health>) -> Vec<Death> {
+    (&players, &health)
+        .iter()
+        .filter(|(_, health)| health.is_dead())
+        .map(|(player, health)| Death {
+            victim: player.player_id,
+            killer: health.killer,
+        })
+        .collect()
+}
+
+// Players in the round with the center of their body.
+fn alive_players(players: View<Player>, transforms: View<Transform>) -> Vec<(PlayerId, Vec2)> {
+    (&players, &transforms)
+        .iter()
+        .map(|(player, transform)| (player.player_id, transform.position + vec2(4., 6.)))
+        .collect()
 }
 
 fn cleanup_world(mut all_storages: AllStoragesViewMut) {
@@ -565,7 +1615,12 @@ fn cleanup_world(mut all_storages: AllStoragesViewMut) {
     all_storages.delete_any::<SparseSet<Projectile>>();
 }
 
-fn respawn_players(connected_players: usize, mut info: UniqueViewMut<GameplayInfo>) -> bool {
+fn respawn_players(
+    connected_players: usize,
+    mut info: UniqueViewMut<GameplayInfo>,
+    tick_rate: UniqueView<TickRate>,
+) -> bool {
+    info.respawn_players_timer.update(tick_rate.frame_time());
     let mut respawn = false;
     if info.respawn_players && info.respawn_players_timer.is_finished() && connected_players > 1 {
         info.respawn_players = false;
@@ -573,7 +1628,3 @@ fn respawn_players(connected_players: usize, mut info: UniqueViewMut<GameplayInf
     }
     respawn
 }
-
-fn get_frame_time() -> f32 {
-    0.0166667
-}
@@ -1,59 +1,120 @@
 use alto_logger::TermLogger;
-use eframe::{egui, epi};
-use shipyard::UniqueViewMut;
+use log::{error, info};
 
-use server::{Game, GameplayConfig};
+use server::config::{ServerOptions, USAGE};
+use server::Game;
+use shared::{
+    ldtk::level_identifiers,
+    transport::{ConditionedTransport, ConditionerHandle, ConditionsPreset, UdpServerTransport},
+    TickRate,
+};
 
-struct ServerApp {
-    game: Game,
-}
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Instant;
+
+#[cfg(feature = "gui")]
+mod gui;
 
 fn main() {
     TermLogger::default().init().unwrap();
 
-    let game = Game::new("127.0.0.1:5000".parse().unwrap()).unwrap();
-    let server_app = ServerApp { game };
-    eframe::run_native(Box::new(server_app));
-}
+    if std::env::args().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
 
-impl epi::App for ServerApp {
-    fn name(&self) -> &str {
-        "Server"
+    let options = match ServerOptions::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            error!("{}", e);
+            println!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let levels = level_identifiers();
+    let config_levels = std::iter::once(&options.config.level).chain(options.config.levels.iter());
+    for level in config_levels {
+        if !levels.contains(level) {
+            error!(
+                "Level {} not found, available levels: {}",
+                level,
+                levels.join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let tick_rate = TickRate(options.config.tick_rate);
+    let transport = match UdpServerTransport::new(options.address, options.config.max_clients) {
+        Ok(transport) => transport,
+        Err(e) => {
+            error!("Failed to start server on {}: {}", options.address, e);
+            std::process::exit(1);
+        }
+    };
+    let preset = options.network_conditions;
+    let conditions = ConditionerHandle::new(preset.conditions());
+    let transport = ConditionedTransport::new(transport, conditions.clone());
+    let mut game = match Game::new(Box::new(transport), options.config) {
+        Ok(game) => game,
+        Err(e) => {
+            error!("Failed to load the levels: {}", e);
+            std::process::exit(1);
+        }
+    };
+    game.enable_discovery(options.address.port());
+    info!("Server listening on {}", options.address);
+    if preset != ConditionsPreset::Off {
+        info!("Simulating {} network conditions", preset.name());
     }
 
-    fn update(&mut self, ctx: &egui::CtxRef, frame: &mut epi::Frame<'_>) {
-        self.game.update();
-
-        ctx.request_repaint();
-
-        egui::CentralPanel::default().show(ctx, |ui| {
-            self.game.world.run(|mut config: UniqueViewMut<GameplayConfig>| {
-                ui.heading("Gameplay Configuration:");
-                let grid = egui::Grid::new("my_grid")
-                    .striped(true)
-                    .spacing([40.0, 4.0]);
-                grid.show(ui, |ui| {
-                    ui.label("Dash speed:");
-                    ui.add(egui::Slider::f32(&mut config.dash_speed, 0.0..=1000.0).text("value"));
-                    ui.end_row();
-
-                    ui.label("Jump speed:");
-                    ui.add(egui::Slider::f32(&mut config.jump_speed, 0.0..=1000.0).text("value"));
-                    ui.end_row();
-
-                    ui.label("Walk speed:");
-                    ui.add(egui::Slider::f32(&mut config.walk_speed, 0.0..=1000.0).text("value"));
-                    ui.end_row();
-
-                    ui.label("Player gravity:");
-                    ui.add(egui::Slider::f32(&mut config.player_gravity, 0.0..=1000.0).text("value"));
-                    ui.end_row();
-                });
-            }).unwrap();
-        });
-
-        // Resize the native window to be just the size we need it to be:
-        frame.set_window_size(ctx.used_size());
+    run(game, tick_rate, options.headless, conditions, preset);
+}
+
+#[cfg(feature = "gui")]
+fn run(
+    game: Game,
+    tick_rate: TickRate,
+    headless: bool,
+    conditions: ConditionerHandle,
+    preset: ConditionsPreset,
+) {
+    if headless {
+        run_headless(game, tick_rate);
+    } else {
+        gui::run(game, conditions, preset);
     }
 }
 
+#[cfg(not(feature = "gui"))]
+fn run(
+    game: Game,
+    tick_rate: TickRate,
+    _headless: bool,
+    _conditions: ConditionerHandle,
+    _preset: ConditionsPreset,
+) {
+    run_headless(game, tick_rate);
+}
+
+fn run_headless(mut game: Game, tick_rate: TickRate) {
+    // Stops on Ctrl-C or SIGTERM so the clients are told about the shutdown
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst)) {
+        error!("Failed to set the shutdown handler: {}", e);
+    }
+
+    let tick_duration = tick_rate.tick_duration();
+    while running.load(Ordering::SeqCst) {
+        let start = Instant::now();
+        game.update();
+        if let Some(remaining) = tick_duration.checked_sub(start.elapsed()) {
+            sleep(remaining);
+        }
+    }
+    game.shutdown();
+}
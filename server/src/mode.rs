@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use glam::Vec2;
+use shared::math::Rect;
+use shared::{GameModeKind, LobbyInfo, PlayerId, PlayersScore, Team};
+
+// Time a dead player waits to spawn again in the modes with respawns.
+const RESPAWN_DELAY: Duration = Duration::from_secs(3);
+// Time a player must hold the hill for each point.
+const HILL_POINT_TIME: Duration = Duration::from_secs(5);
+
+/// Player killed in the running round.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Death {
+    pub victim: PlayerId,
+    // None when the caster of the fireball already left.
+    pub killer: Option<PlayerId>,
+}
+
+/// What the game mode sees of the running round each tick.
+pub(crate) struct RoundState<'a> {
+    // Players alive with the center of their body.
+    pub alive: &'a [(PlayerId, Vec2)],
+    pub hill: Option<Rect>,
+    pub tick_duration: Duration,
+}
+
+/// Rules of the rounds of a match: how players score, when they spawn
+/// and when the round ends. The match itself ends with the `MatchRules`.
+pub(crate) trait GameMode {
+    fn kind(&self) -> GameModeKind;
+
+    // Time a dead player waits to spawn again in the running round,
+    // none to wait for the next round.
+    fn respawn_delay(&self) -> Option<Duration> {
+        None
+    }
+
+    // Scores the death of a player, the round keeps running.
+    fn player_died(&mut self, _death: Death, _lobby_info: &LobbyInfo, _score: &mut PlayersScore) {}
+
+    // Called each tick of a running round, returns true when the round is over.
+    fn update(&mut self, round: &RoundState, score: &mut PlayersScore) -> bool;
+}
+
+pub(crate) fn new_game_mode(kind: GameModeKind) -> Box<dyn GameMode> {
+    match kind {
+        GameModeKind::LastWizardStanding => Box::new(LastWizardStanding),
+        GameModeKind::Deathmatch => Box::new(Deathmatch { teams: false }),
+        GameModeKind::TeamDeathmatch => Box::new(Deathmatch { teams: true }),
+        GameModeKind::KingOfTheHill => Box::new(KingOfTheHill::default()),
+    }
+}
+
+// Scores stop at the maximum in matches without a score limit.
+fn add_point(score: &mut PlayersScore, player_id: PlayerId) {
+    let points = score.score.entry(player_id).or_insert(0);
+    *points = points.saturating_add(1);
+    score.updated = true;
+}
+
+fn add_team_point(score: &mut PlayersScore, team: Team) {
+    let points = score.team_score.entry(team).or_insert(0);
+    *points = points.saturating_add(1);
+    score.updated = true;
+}
+
+// The last player alive wins the round, everyone spawns again in the next one.
+struct LastWizardStanding;
+
+impl GameMode for LastWizardStanding {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::LastWizardStanding
+    }
+
+    fn update(&mut self, round: &RoundState, score: &mut PlayersScore) -> bool {
+        if round.alive.len() > 1 {
+            return false;
+        }
+        if let Some((player_id, _)) = round.alive.first() {
+            add_point(score, *player_id);
+        }
+        true
+    }
+}
+
+// Each kill of an enemy scores, the round lasts the whole match.
+struct Deathmatch {
+    teams: bool,
+}
+
+impl GameMode for Deathmatch {
+    fn kind(&self) -> GameModeKind {
+        if self.teams {
+            GameModeKind::TeamDeathmatch
+        } else {
+            GameModeKind::Deathmatch
+        }
+    }
+
+    fn respawn_delay(&self) -> Option<Duration> {
+        Some(RESPAWN_DELAY)
+    }
+
+    fn player_died(&mut self, death: Death, lobby_info: &LobbyInfo, score: &mut PlayersScore) {
+        let killer = match death.killer {
+            Some(killer) if killer != death.victim => killer,
+            _ => return,
+        };
+        let team = lobby_info.team(killer);
+        if self.teams && team.is_some() && team == lobby_info.team(death.victim) {
+            return;
+        }
+        add_point(score, killer);
+        if let Some(team) = team.filter(|_| self.teams) {
+            add_team_point(score, team);
+        }
+    }
+
+    fn update(&mut self, _round: &RoundState, _score: &mut PlayersScore) -> bool {
+        false
+    }
+}
+
+// Players score while they are alone on the hill of the level,
+// levels without a hill are only ended by the time limit.
+#[derive(Default)]
+struct KingOfTheHill {
+    // Time held on the hill not yet scored.
+    hold_time: HashMap<PlayerId, Duration>,
+}
+
+impl GameMode for KingOfTheHill {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::KingOfTheHill
+    }
+
+    fn respawn_delay(&self) -> Option<Duration> {
+        Some(RESPAWN_DELAY)
+    }
+
+    fn update(&mut self, round: &RoundState, score: &mut PlayersScore) -> bool {
+        let hill = match round.hill {
+            Some(hill) => hill,
+            None => return false,
+        };
+        let mut on_hill = round
+            .alive
+            .iter()
+            .filter(|(_, position)| hill.contains(*position));
+        // Contested while more than one player is on it
+        if let (Some((player_id, _)), None) = (on_hill.next(), on_hill.next()) {
+            let hold_time = self.hold_time.entry(*player_id).or_default();
+            *hold_time += round.tick_duration;
+            if *hold_time >= HILL_POINT_TIME {
+                *hold_time -= HILL_POINT_TIME;
+                add_point(score, *player_id);
+            }
+        }
+        false
+    }
+}
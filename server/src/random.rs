@@ -0,0 +1,12 @@
+/// Random number from the generator of the operating system, unpredictable
+/// enough for the session tokens.
+pub(crate) fn random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).expect("Failed to get random bytes from the system.");
+    u64::from_le_bytes(bytes)
+}
+
+/// Random index in `0..len`, `len` must not be zero.
+pub(crate) fn random_index(len: usize) -> usize {
+    (random_u64() % len as u64) as usize
+}
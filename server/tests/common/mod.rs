@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bincode::{deserialize, serialize};
+use glam::Vec2;
+use shipyard::*;
+
+use server::{Game, Scene, ServerConfig};
+use shared::{
+    message::{
+        ClientAction, Handshake, HandshakeResponse, JoinRequest, ServerInfo, ServerMessages,
+    },
+    physics::Physics,
+    player::{Player, PlayerInput},
+    transport::{memory_transport, ClientTransport, MemoryClientTransport, MemoryConnector},
+    Channel, Health, LobbyInfo, PlayerId, PlayerStats, PlayersScore, PlayersStats, Team, TickRate,
+};
+
+// Time between the end of a round and the respawn of the players, with some margin.
+pub const ROUND_RESTART_TICKS: u32 = 4 * 60;
+
+/// Simulated client connected to the server in memory.
+pub struct TestClient {
+    transport: MemoryClientTransport,
+    // Inputs sent to the server, one each tick.
+    inputs: VecDeque<PlayerInput>,
+    sequence: u32,
+    // Answer of the server to the handshake, received before any other message.
+    pub handshake_response: Option<HandshakeResponse>,
+    pub messages: Vec<ServerMessages>,
+    // False once the server closed the connection.
+    pub connected: bool,
+}
+
+impl TestClient {
+    /// Identity assigned by the server, known after the first tick.
+    pub fn player_id(&self) -> PlayerId {
+        self.server_info().expect("Client not connected.").player_id
+    }
+
+    pub fn send_action(&mut self, action: ClientAction) {
+        let message = serialize(&action).unwrap();
+        self.transport
+            .send_message(Channel::Reliable.id(), message)
+            .unwrap();
+    }
+
+    /// Sends a message as is, to test how the server handles bad messages.
+    pub fn send_raw(&mut self, channel: Channel, message: Vec<u8>) {
+        self.transport.send_message(channel.id(), message).unwrap();
+    }
+
+    /// Queues inputs to be sent to the server, one each tick.
+    pub fn script(&mut self, inputs: impl IntoIterator<Item = PlayerInput>) {
+        self.inputs.extend(inputs);
+    }
+
+    /// Last lobby received from the server.
+    pub fn lobby(&self) -> Option<&LobbyInfo> {
+        self.messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                ServerMessages::UpdateLobby(lobby_info) => Some(lobby_info),
+                _ => None,
+            })
+    }
+
+    pub fn received_start_gameplay(&self) -> bool {
+        self.messages
+            .iter()
+            .any(|message| matches!(message, ServerMessages::StartGameplay))
+    }
+
+    /// Levels the server changed to, in order.
+    pub fn level_changes(&self) -> Vec<&str> {
+        self.messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessages::ChangeLevel(level) => Some(level.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Last server info received, sent again when resuming a session.
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                ServerMessages::ServerInfo(server_info) => Some(server_info),
+                _ => None,
+            })
+    }
+
+    fn send_input(&mut self) {
+        if let Some(mut input) = self.inputs.pop_front() {
+            self.sequence += 1;
+            input.sequence = self.sequence;
+            let message = serialize(&input).unwrap();
+            self.transport
+                .send_message(Channel::ReliableCritical.id(), message)
+                .unwrap();
+        }
+    }
+
+    fn receive_messages(&mut self) {
+        // Messages sent before the server closed the connection are still received
+        self.connected = self.transport.update(Duration::ZERO).is_ok();
+        while let Some(message) = self.transport.receive_message(Channel::Reliable.id()) {
+            if self.handshake_response.is_none() {
+                self.handshake_response = Some(deserialize(&message).unwrap());
+            } else {
+                self.messages.push(deserialize(&message).unwrap());
+            }
+        }
+        // Server frames are not decoded, the tests inspect the server world instead
+        while self
+            .transport
+            .receive_message(Channel::Unreliable.id())
+            .is_some()
+        {}
+    }
+}
+
+/// Server with simulated clients, advanced one tick at a time.
+pub struct TestServer {
+    pub game: Game,
+    pub clients: Vec<TestClient>,
+    connector: MemoryConnector,
+    tick_duration: Duration,
+}
+
+impl TestServer {
+    pub fn new(clients: usize) -> Self {
+        Self::with_config(clients, ServerConfig::default())
+    }
+
+    pub fn with_config(clients: usize, config: ServerConfig) -> Self {
+        let tick_duration = TickRate(config.tick_rate).tick_duration();
+        let (transport, connector) = memory_transport();
+        let game = Game::new(Box::new(transport), config).unwrap();
+
+        let mut server = Self {
+            game,
+            clients: vec![],
+            connector,
+            tick_duration,
+        };
+        for _ in 0..clients {
+            server.connect();
+        }
+        server.tick();
+        server
+    }
+
+    /// Connects a new client, it is accepted in the next tick.
+    pub fn connect(&mut self) -> usize {
+        self.connect_with(Handshake::new().unwrap())
+    }
+
+    pub fn connect_with(&mut self, handshake: Handshake) -> usize {
+        let mut client = TestClient {
+            transport: self.connector.connect(),
+            inputs: VecDeque::new(),
+            sequence: 0,
+            handshake_response: None,
+            messages: vec![],
+            connected: true,
+        };
+        client.send_raw(Channel::Reliable, serialize(&handshake).unwrap());
+        self.clients.push(client);
+        self.clients.len() - 1
+    }
+
+    /// Drops the client, the server notices it in the next tick.
+    pub fn disconnect(&mut self, client: usize) {
+        self.clients.remove(client);
+    }
+
+    /// Drops the client and connects a new one with its session token.
+    pub fn reconnect(&mut self, client: usize) -> usize {
+        let session_token = self.clients[client]
+            .server_info()
+            .expect("Client not connected.")
+            .session_token;
+        self.disconnect(client);
+        self.tick();
+
+        let client = self.connect();
+        self.tick();
+        self.clients[client].send_action(ClientAction::Join(JoinRequest {
+            name: String::new(),
+            session_token: Some(session_token),
+        }));
+        self.tick();
+        client
+    }
+
+    pub fn player_id(&self, client: usize) -> PlayerId {
+        self.clients[client].player_id()
+    }
+
+    pub fn tick(&mut self) {
+        for client in self.clients.iter_mut() {
+            client.send_input();
+        }
+        self.game.update_with_duration(self.tick_duration);
+        for client in self.clients.iter_mut() {
+            client.receive_messages();
+        }
+    }
+
+    pub fn run_ticks(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    /// Readies all clients and waits for the players of the first round to spawn.
+    pub fn start_gameplay(&mut self) {
+        for client in self.clients.iter_mut() {
+            client.send_action(ClientAction::LobbyReady);
+        }
+        self.run_ticks(2);
+        assert_eq!(self.game.scene(), Scene::Gameplay);
+        self.run_ticks(ROUND_RESTART_TICKS);
+    }
+
+    pub fn players_count(&self) -> usize {
+        self.game
+            .world
+            .run(|players: View<Player>| players.iter().count())
+            .unwrap()
+    }
+
+    pub fn player_entity(&self, client: usize) -> Option<EntityId> {
+        let player_id = self.player_id(client);
+        self.game
+            .world
+            .run(|players: View<Player>| {
+                players
+                    .iter()
+                    .with_id()
+                    .find(|(_, player)| player.player_id == player_id)
+                    .map(|(entity_id, _)| entity_id)
+            })
+            .unwrap()
+    }
+
+    pub fn health(&self, client: usize) -> Option<Health> {
+        let entity_id = self.player_entity(client)?;
+        self.game
+            .world
+            .run(|health: View<Health>| (&health).get(entity_id).ok().cloned())
+            .unwrap()
+    }
+
+    pub fn score(&self, client: usize) -> Option<u8> {
+        let player_id = self.player_id(client);
+        self.game
+            .world
+            .run(|players_score: UniqueView<PlayersScore>| {
+                players_score.score.get(&player_id).copied()
+            })
+            .unwrap()
+    }
+
+    pub fn stats(&self, client: usize) -> PlayerStats {
+        let player_id = self.player_id(client);
+        self.game
+            .world
+            .run(|players_stats: UniqueView<PlayersStats>| players_stats.get(player_id))
+            .unwrap()
+    }
+
+    pub fn team_score(&self, team: Team) -> Option<u8> {
+        self.game
+            .world
+            .run(|players_score: UniqueView<PlayersScore>| {
+                players_score.team_score.get(&team).copied()
+            })
+            .unwrap()
+    }
+
+    pub fn scores_count(&self) -> usize {
+        self.game
+            .world
+            .run(|players_score: UniqueView<PlayersScore>| players_score.score.len())
+            .unwrap()
+    }
+
+    pub fn player_position(&self, client: usize) -> Vec2 {
+        let entity_id = self.player_entity(client).expect("Player not spawned.");
+        self.game
+            .world
+            .run(|physics: UniqueView<Physics>| physics.actor_pos(entity_id))
+            .unwrap()
+    }
+
+    pub fn place_player(&mut self, client: usize, position: Vec2) {
+        let entity_id = self.player_entity(client).expect("Player not spawned.");
+        self.game
+            .world
+            .run(|mut physics: UniqueViewMut<Physics>| {
+                physics.set_actor_position(&entity_id, position)
+            })
+            .unwrap();
+    }
+
+    /// Places the target over the caster and casts a fireball at it,
+    /// the fireball hits the target in the next tick.
+    pub fn hit_with_fireball(&mut self, caster: usize, target: usize) {
+        let position = self.player_position(caster);
+        self.place_player(target, position);
+
+        let direction = Vec2::X;
+        self.clients[caster].script(vec![
+            PlayerInput {
+                fire: true,
+                direction,
+                ..Default::default()
+            },
+            PlayerInput {
+                fire: false,
+                direction,
+                ..Default::default()
+            },
+        ]);
+        self.run_ticks(3);
+    }
+}
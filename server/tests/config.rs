@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::PathBuf;
+
+use server::config::{ConfigError, ServerOptions};
+use shared::{ldtk::LevelRotation, transport::ConditionsPreset, GameModeKind};
+
+fn parse_args(args: &[&str]) -> Result<ServerOptions, ConfigError> {
+    ServerOptions::from_args(args.iter().map(|arg| arg.to_string()))
+}
+
+// Config file in the temporary directory, named after the test using it.
+fn config_file(name: &str, content: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("wizardfall-{}-{}.conf", name, std::process::id()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn no_arguments_give_the_default_options() {
+    let options = parse_args(&[]).unwrap();
+    assert_eq!(options.address, "127.0.0.1:5000".parse().unwrap());
+    assert!(!options.headless);
+    assert_eq!(options.config.tick_rate, 60);
+    assert_eq!(options.config.send_rate, 30);
+}
+
+#[test]
+fn command_line_options_are_parsed() {
+    let options = parse_args(&[
+        "--address",
+        "0.0.0.0:6000",
+        "--max-clients",
+        "8",
+        "--levels",
+        "First, Second,",
+        "--level-rotation",
+        "random",
+        "--mode",
+        "team-deathmatch",
+        "--network-conditions",
+        "wi-fi",
+        "--headless",
+        "--score-limit",
+        "3",
+    ])
+    .unwrap();
+    assert_eq!(options.address, "0.0.0.0:6000".parse().unwrap());
+    assert!(options.headless);
+    assert_eq!(options.network_conditions, ConditionsPreset::WiFi);
+    assert_eq!(options.config.max_clients, 8);
+    assert_eq!(options.config.levels, vec!["First", "Second"]);
+    assert_eq!(options.config.level_rotation, LevelRotation::Random);
+    assert_eq!(options.config.mode, GameModeKind::TeamDeathmatch);
+    assert_eq!(options.config.rules.score_limit, 3);
+}
+
+#[test]
+fn command_line_overrides_the_config_file() {
+    let path = config_file(
+        "precedence",
+        "# Comments and empty lines are skipped\n\
+         \n\
+         name = From the file\n\
+         tick_rate = 30\n\
+         send_rate=10\n",
+    );
+    let path = path.to_str().unwrap();
+
+    // The file is read first wherever it is in the arguments
+    let options = parse_args(&["--send-rate", "15", "--config", path]).unwrap();
+    assert_eq!(options.config.name, "From the file");
+    assert_eq!(options.config.tick_rate, 30);
+    assert_eq!(options.config.send_rate, 15);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unknown_options_are_rejected() {
+    assert!(matches!(
+        parse_args(&["--color", "blue"]),
+        Err(ConfigError::UnknownOption(option)) if option == "color"
+    ));
+    assert!(matches!(
+        parse_args(&["max-clients", "8"]),
+        Err(ConfigError::UnknownOption(option)) if option == "max-clients"
+    ));
+    assert!(matches!(
+        parse_args(&["--max-clients"]),
+        Err(ConfigError::MissingValue(option)) if option == "--max-clients"
+    ));
+
+    let path = config_file("unknown", "max_clients = 8\ncolor = blue\n");
+    let result = parse_args(&["--config", path.to_str().unwrap()]);
+    assert!(matches!(result, Err(ConfigError::UnknownOption(option)) if option == "color"));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    let invalid = |args: &[&str], expected_key: &str| match parse_args(args) {
+        Err(ConfigError::InvalidValue(key, _)) => assert_eq!(key, expected_key),
+        other => panic!("{:?} gave {:?}", args, other),
+    };
+    invalid(&["--tick-rate", "fast"], "tick_rate");
+    invalid(&["--address", "localhost"], "address");
+    invalid(&["--mode", "capture-the-flag"], "mode");
+    invalid(&["--level-rotation", "shuffle"], "level_rotation");
+    invalid(&["--friendly-fire", "yes"], "friendly_fire");
+    invalid(&["--network-conditions", "dial-up"], "network_conditions");
+    invalid(&["--max-clients", "-1"], "max_clients");
+
+    let path = config_file("invalid_line", "name = Wizards\nheadless\n");
+    let result = parse_args(&["--config", path.to_str().unwrap()]);
+    assert!(matches!(result, Err(ConfigError::InvalidLine(_, 2))));
+    fs::remove_file(path).unwrap();
+
+    let path = std::env::temp_dir().join("wizardfall-missing.conf");
+    let result = parse_args(&["--config", path.to_str().unwrap()]);
+    assert!(matches!(result, Err(ConfigError::Io(_, _))));
+}
+
+#[test]
+fn rates_are_validated() {
+    let invalid = |args: &[&str], expected_key: &str| match parse_args(args) {
+        Err(ConfigError::InvalidValue(key, _)) => assert_eq!(key, expected_key),
+        other => panic!("{:?} gave {:?}", args, other),
+    };
+    invalid(&["--tick-rate", "0"], "tick_rate");
+    invalid(&["--send-rate", "0"], "send_rate");
+    // The default tick rate is 60
+    invalid(&["--send-rate", "61"], "send_rate");
+    invalid(&["--tick-rate", "20", "--send-rate", "30"], "send_rate");
+    invalid(&["--max-clients", "0"], "max_clients");
+
+    let options = parse_args(&["--tick-rate", "20", "--send-rate", "20"]).unwrap();
+    assert_eq!(options.config.tick_rate, 20);
+    assert_eq!(options.config.send_rate, 20);
+}
@@ -0,0 +1,789 @@
+mod common;
+
+use common::{TestServer, ROUND_RESTART_TICKS};
+use glam::Vec2;
+use server::{Scene, ServerConfig};
+use shared::{
+    ldtk::{level_files, LevelRotation, DEFAULT_LEVEL, PROJECT_FILE},
+    message::{
+        ClientAction, Handshake, HandshakeResponse, JoinRequest, ServerMessages, PROTOCOL_VERSION,
+    },
+    player::{GameplayConfig, PlayerInput},
+    Channel, GameModeKind, MatchRules, Team,
+};
+use shipyard::UniqueViewMut;
+
+// Fireball cooldown of the players, with some margin.
+const FIREBALL_COOLDOWN_TICKS: u32 = 2 * 60;
+
+#[test]
+fn lobby_starts_gameplay_when_all_clients_are_ready() {
+    let mut server = TestServer::new(2);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+
+    server.clients[0].send_action(ClientAction::LobbyReady);
+    server.run_ticks(2);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+
+    server.clients[1].send_action(ClientAction::LobbyReady);
+    server.run_ticks(2);
+    assert_eq!(server.game.scene(), Scene::Gameplay);
+    assert!(server.clients.iter().all(|c| c.received_start_gameplay()));
+}
+
+#[test]
+fn lobby_ready_toggles() {
+    let mut server = TestServer::new(2);
+    server.clients[0].send_action(ClientAction::LobbyReady);
+    server.clients[0].send_action(ClientAction::LobbyReady);
+    server.clients[1].send_action(ClientAction::LobbyReady);
+    server.run_ticks(2);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+}
+
+#[test]
+fn single_client_stays_in_lobby() {
+    let mut server = TestServer::new(1);
+    server.clients[0].send_action(ClientAction::LobbyReady);
+    server.run_ticks(2);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+}
+
+#[test]
+fn players_spawn_when_the_first_round_starts() {
+    let mut server = TestServer::new(3);
+    server.start_gameplay();
+
+    assert_eq!(server.players_count(), 3);
+    for client in 0..3 {
+        let health = server.health(client).unwrap();
+        assert_eq!(health.current, health.max);
+        assert_eq!(server.score(client), Some(0));
+    }
+}
+
+#[test]
+fn fireball_damages_other_player() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+
+    let caster_health = server.health(0).unwrap();
+    assert_eq!(caster_health.current, caster_health.max);
+    let target_health = server.health(1).unwrap();
+    assert_eq!(target_health.current, target_health.max - 1);
+}
+
+#[test]
+fn last_player_alive_scores_and_round_restarts() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+
+    // The dead player is removed and the round ends
+    assert_eq!(server.score(0), Some(1));
+    assert_eq!(server.score(1), Some(0));
+    assert_eq!(server.players_count(), 0);
+
+    server.run_ticks(ROUND_RESTART_TICKS);
+    assert_eq!(server.players_count(), 2);
+    let health = server.health(1).unwrap();
+    assert_eq!(health.current, health.max);
+}
+
+#[test]
+fn disconnected_client_is_removed() {
+    let config = ServerConfig {
+        reconnect_timeout: 0,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(3, config);
+    server.start_gameplay();
+
+    server.disconnect(2);
+    server.run_ticks(2);
+
+    // The round goes on while more than one player is alive
+    assert_eq!(server.players_count(), 2);
+    assert_eq!(server.scores_count(), 2);
+}
+
+#[test]
+fn late_client_spectates_until_the_next_round() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+
+    let late_client = server.connect();
+    server.tick();
+    let server_info = server.clients[late_client].server_info().unwrap();
+    assert_eq!(server_info.scene, Scene::Gameplay);
+    assert_eq!(server.score(late_client), Some(0));
+    assert!(server.player_entity(late_client).is_none());
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(ROUND_RESTART_TICKS);
+
+    assert_eq!(server.players_count(), 3);
+    assert!(server.player_entity(late_client).is_some());
+}
+
+#[test]
+fn late_client_spawns_right_away_when_allowed() {
+    let config = ServerConfig {
+        late_join_spawn: true,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    let late_client = server.connect();
+    server.tick();
+    assert_eq!(server.players_count(), 3);
+    assert!(server.player_entity(late_client).is_some());
+}
+
+#[test]
+fn clients_are_shown_by_their_name() {
+    let mut server = TestServer::new(2);
+    let name = |name: &str| {
+        ClientAction::Join(JoinRequest {
+            name: name.to_string(),
+            session_token: None,
+        })
+    };
+    server.clients[0].send_action(name("Merlin"));
+    server.clients[1].send_action(name("  \n "));
+    server.run_ticks(2);
+
+    let player_0 = server.player_id(0);
+    let player_1 = server.player_id(1);
+    assert_ne!(player_0, player_1);
+    for client in server.clients.iter() {
+        let lobby = client.lobby().unwrap();
+        assert_eq!(lobby.player_name(player_0), "Merlin");
+        assert_eq!(
+            lobby.player_name(player_1),
+            format!("Player {}", player_1.0)
+        );
+    }
+}
+
+#[test]
+fn reconnected_client_keeps_its_player_and_score() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+
+    let player_id = server.player_id(0);
+    let client = server.reconnect(0);
+    assert_eq!(server.player_id(client), player_id);
+    assert_eq!(server.score(client), Some(1));
+    // The player created for the new connection is discarded
+    assert_eq!(server.scores_count(), 2);
+
+    server.run_ticks(ROUND_RESTART_TICKS);
+    assert_eq!(server.players_count(), 2);
+    assert!(server.player_entity(client).is_some());
+}
+
+#[test]
+fn clients_get_different_session_tokens() {
+    let server = TestServer::new(4);
+    let mut tokens: Vec<u64> = server
+        .clients
+        .iter()
+        .map(|client| client.server_info().unwrap().session_token.0)
+        .collect();
+    tokens.sort_unstable();
+    tokens.dedup();
+    assert_eq!(tokens.len(), 4);
+}
+
+#[test]
+fn reconnected_player_respawns_in_deathmatch() {
+    let config = ServerConfig {
+        mode: GameModeKind::Deathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    let client = server.reconnect(1);
+    assert_eq!(server.players_count(), 1);
+    // The round never ends in deathmatch, the player spawns after the respawn delay
+    server.run_ticks(ROUND_RESTART_TICKS);
+    assert_eq!(server.players_count(), 2);
+    assert!(server.player_entity(client).is_some());
+}
+
+#[test]
+fn disconnected_player_is_removed_after_the_timeout() {
+    let config = ServerConfig {
+        reconnect_timeout: 1,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    let player_id = server.player_id(1);
+
+    server.disconnect(1);
+    server.tick();
+    let lobby = server.clients[0].lobby().unwrap();
+    assert!(!lobby.clients[&player_id].connected);
+    assert_eq!(server.scores_count(), 2);
+
+    server.run_ticks(2 * 60);
+    let lobby = server.clients[0].lobby().unwrap();
+    assert!(!lobby.clients.contains_key(&player_id));
+    assert_eq!(server.scores_count(), 1);
+}
+
+#[test]
+fn client_sending_bad_messages_is_kicked() {
+    let config = ServerConfig {
+        max_strikes: 3,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+
+    for _ in 0..3 {
+        server.clients[1].send_raw(Channel::Reliable, vec![255; 4]);
+    }
+    server.tick();
+    assert!(server.clients[1].connected);
+
+    server.clients[1].send_raw(Channel::Reliable, vec![255; 4]);
+    server.tick();
+    assert!(server.clients[1]
+        .messages
+        .iter()
+        .any(|message| matches!(message, ServerMessages::Kicked { .. })));
+    assert_eq!(server.scores_count(), 1);
+
+    // The connection is closed once the reason had time to arrive
+    server.run_ticks(2 * 60);
+    assert!(!server.clients[1].connected);
+    assert!(server.clients[0].connected);
+}
+
+#[test]
+fn input_with_invalid_direction_is_dropped() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+
+    let position = server.player_position(0);
+    server.clients[0].script(vec![PlayerInput {
+        right: true,
+        direction: Vec2::new(f32::NAN, 0.),
+        ..Default::default()
+    }]);
+    server.run_ticks(2);
+    assert_eq!(server.player_position(0), position);
+    assert!(server.clients[0].connected);
+}
+
+#[test]
+fn actions_over_the_rate_limit_are_dropped() {
+    let config = ServerConfig {
+        max_actions_per_second: 2,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+
+    // The third toggle is dropped, otherwise both clients would be ready
+    for _ in 0..3 {
+        server.clients[0].send_action(ClientAction::LobbyReady);
+    }
+    server.clients[1].send_action(ClientAction::LobbyReady);
+    server.run_ticks(2);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+}
+
+#[test]
+fn client_of_another_version_is_rejected() {
+    let mut server = TestServer::new(1);
+    let handshake = Handshake {
+        protocol_version: PROTOCOL_VERSION + 1,
+        ..Handshake::new().unwrap()
+    };
+    let client = server.connect_with(handshake);
+    server.tick();
+
+    match &server.clients[client].handshake_response {
+        Some(HandshakeResponse::Rejected(reason)) => assert!(reason.contains("version")),
+        response => panic!("Unexpected handshake response: {:?}", response),
+    }
+    assert!(server.clients[client].messages.is_empty());
+    assert_eq!(server.scores_count(), 1);
+
+    server.run_ticks(2 * 60);
+    assert!(!server.clients[client].connected);
+}
+
+#[test]
+fn client_with_other_content_is_rejected() {
+    let mut server = TestServer::new(1);
+    let handshake = Handshake::new().unwrap();
+    let client = server.connect_with(Handshake {
+        content_hash: handshake.content_hash + 1,
+        ..handshake
+    });
+    server.tick();
+
+    assert!(matches!(
+        server.clients[client].handshake_response,
+        Some(HandshakeResponse::Rejected(_))
+    ));
+    assert_eq!(
+        server.clients[0].handshake_response,
+        Some(HandshakeResponse::Accepted)
+    );
+}
+
+#[test]
+fn content_hash_covers_the_external_level_files() {
+    let files = level_files().unwrap();
+    assert!(files.iter().any(|path| path.ends_with(PROJECT_FILE)));
+    assert!(files.iter().any(|path| path.ends_with(".ldtkl")));
+}
+
+#[test]
+fn clients_are_accepted_after_the_gameplay_values_change() {
+    let mut server = TestServer::new(1);
+    let gameplay = GameplayConfig {
+        walk_speed: 1000.,
+        ..Default::default()
+    };
+    server
+        .game
+        .world
+        .run(|mut config: UniqueViewMut<GameplayConfig>| *config = gameplay.clone())
+        .unwrap();
+    server.tick();
+
+    // Clients only know the default values, they get the others from the server
+    let client = server.connect();
+    server.tick();
+    assert_eq!(
+        server.clients[client].handshake_response,
+        Some(HandshakeResponse::Accepted)
+    );
+    assert_eq!(
+        server.clients[client]
+            .server_info()
+            .unwrap()
+            .gameplay_config,
+        gameplay
+    );
+}
+
+#[test]
+fn leaving_client_is_removed_right_away() {
+    let mut server = TestServer::new(3);
+    server.start_gameplay();
+
+    server.clients[2].send_action(ClientAction::Leave);
+    server.tick();
+    assert!(!server.clients[2].connected);
+    assert_eq!(server.players_count(), 2);
+    assert_eq!(server.scores_count(), 2);
+}
+
+#[test]
+fn clients_are_told_about_the_shutdown() {
+    let mut server = TestServer::new(2);
+    server.game.shutdown();
+    server.tick();
+
+    for client in server.clients.iter() {
+        assert!(matches!(
+            client.messages.last(),
+            Some(ServerMessages::Shutdown)
+        ));
+        assert!(!client.connected);
+    }
+}
+
+#[test]
+fn players_vote_for_the_next_level() {
+    let config = ServerConfig {
+        level_rotation: LevelRotation::Vote,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    let levels = server.clients[0].server_info().unwrap().levels.clone();
+    assert_eq!(
+        levels,
+        vec![DEFAULT_LEVEL.to_string(), "Second".to_string()]
+    );
+
+    let player_0 = server.player_id(0);
+    let player_1 = server.player_id(1);
+    server.clients[0].send_action(ClientAction::VoteLevel("Second".to_string()));
+    server.clients[1].send_action(ClientAction::VoteLevel("Missing".to_string()));
+    server.run_ticks(2);
+    let lobby = server.clients[0].lobby().unwrap();
+    assert_eq!(
+        lobby.clients[&player_0].level_vote,
+        Some("Second".to_string())
+    );
+    assert_eq!(lobby.clients[&player_1].level_vote, None);
+
+    // Votes are counted when the round ends
+    server.start_gameplay();
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    for client in server.clients.iter() {
+        assert_eq!(client.level_changes(), vec!["Second"]);
+    }
+    let lobby = server.clients[0].lobby().unwrap();
+    assert_eq!(lobby.clients[&player_0].level_vote, None);
+}
+
+#[test]
+fn sequential_rotation_plays_the_levels_in_order() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    assert_eq!(server.clients[0].level_changes(), vec!["Second"]);
+
+    server.run_ticks(ROUND_RESTART_TICKS);
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    assert_eq!(
+        server.clients[0].level_changes(),
+        vec!["Second", DEFAULT_LEVEL]
+    );
+}
+
+#[test]
+fn random_rotation_changes_the_level() {
+    let config = ServerConfig {
+        level_rotation: LevelRotation::Random,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    // The current level is left out, the other one is the only pick
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    assert_eq!(server.clients[0].level_changes(), vec!["Second"]);
+}
+
+#[test]
+fn fixed_rotation_keeps_the_level() {
+    let config = ServerConfig {
+        level_rotation: LevelRotation::Fixed,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(ROUND_RESTART_TICKS);
+    assert!(server.clients[0].level_changes().is_empty());
+    assert_eq!(server.players_count(), 2);
+}
+
+#[test]
+fn match_ends_at_the_score_limit() {
+    let config = ServerConfig {
+        rules: MatchRules {
+            score_limit: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+
+    let standings = server.clients[1]
+        .messages
+        .iter()
+        .find_map(|message| match message {
+            ServerMessages::MatchOver { standings, .. } => Some(standings.clone()),
+            _ => None,
+        });
+    let standings = standings.unwrap();
+    assert_eq!(
+        standings.iter().map(|s| s.score).collect::<Vec<u8>>(),
+        vec![1, 0]
+    );
+    assert_eq!(standings[0].player_id, server.player_id(0));
+
+    // The next match starts from scratch once everyone is ready again
+    assert_eq!(server.score(0), Some(0));
+    let lobby = server.clients[0].lobby().unwrap();
+    assert!(lobby.clients.values().all(|c| !c.ready));
+    server.run_ticks(ROUND_RESTART_TICKS);
+    assert_eq!(server.players_count(), 0);
+}
+
+#[test]
+fn match_ends_when_the_time_is_up() {
+    let config = ServerConfig {
+        rules: MatchRules {
+            score_limit: 0,
+            time_limit: 10,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+    assert_eq!(server.players_count(), 2);
+
+    server.run_ticks(7 * 60);
+    assert_eq!(server.game.scene(), Scene::Lobby);
+    assert_eq!(server.players_count(), 0);
+    assert!(server.clients[0]
+        .messages
+        .iter()
+        .any(|message| matches!(message, ServerMessages::MatchOver { .. })));
+}
+
+#[test]
+fn players_vote_for_the_mode_of_the_match() {
+    let mut server = TestServer::new(2);
+    for client in server.clients.iter_mut() {
+        client.send_action(ClientAction::VoteMode(GameModeKind::Deathmatch));
+    }
+    server.start_gameplay();
+    assert_eq!(
+        server.clients[0].lobby().unwrap().mode,
+        GameModeKind::Deathmatch
+    );
+}
+
+#[test]
+fn deathmatch_scores_kills_and_respawns_players() {
+    let config = ServerConfig {
+        mode: GameModeKind::Deathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+
+    // The round goes on without the dead player until it respawns
+    assert_eq!(server.score(0), Some(1));
+    assert_eq!(server.players_count(), 1);
+    server.run_ticks(ROUND_RESTART_TICKS);
+    assert_eq!(server.players_count(), 2);
+    assert_eq!(server.score(0), Some(1));
+}
+
+#[test]
+fn player_alone_on_the_hill_scores() {
+    let config = ServerConfig {
+        mode: GameModeKind::KingOfTheHill,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    server.place_player(0, Vec2::new(164., 160.));
+    server.place_player(1, Vec2::new(20., 24.));
+    server.run_ticks(6 * 60);
+    assert_eq!(server.score(0), Some(1));
+    assert_eq!(server.score(1), Some(0));
+}
+
+#[test]
+fn players_keep_their_team_pick_and_the_rest_are_balanced() {
+    let config = ServerConfig {
+        mode: GameModeKind::TeamDeathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(4, config);
+    server.clients[0].send_action(ClientAction::PickTeam(Some(Team::Blue)));
+    server.clients[1].send_action(ClientAction::PickTeam(Some(Team::Blue)));
+    server.start_gameplay();
+
+    let lobby = server.clients[0].lobby().unwrap();
+    let team = |client: usize| lobby.clients[&server.player_id(client)].team;
+    assert_eq!(team(0), Some(Team::Blue));
+    assert_eq!(team(1), Some(Team::Blue));
+    assert_eq!(team(2), Some(Team::Red));
+    assert_eq!(team(3), Some(Team::Red));
+}
+
+#[test]
+fn team_picks_are_capped_at_half_of_the_players() {
+    let config = ServerConfig {
+        mode: GameModeKind::TeamDeathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(5, config);
+    for client in server.clients.iter_mut() {
+        client.send_action(ClientAction::PickTeam(Some(Team::Blue)));
+    }
+    server.start_gameplay();
+
+    let lobby = server.clients[0].lobby().unwrap();
+    let team = |client: usize| lobby.clients[&server.player_id(client)].team;
+    for client in 0..3 {
+        assert_eq!(team(client), Some(Team::Blue));
+    }
+    assert_eq!(team(3), Some(Team::Red));
+    assert_eq!(team(4), Some(Team::Red));
+}
+
+#[test]
+fn fireballs_go_through_teammates_without_friendly_fire() {
+    let config = ServerConfig {
+        mode: GameModeKind::TeamDeathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(4, config);
+    server.clients[0].send_action(ClientAction::PickTeam(Some(Team::Red)));
+    server.clients[1].send_action(ClientAction::PickTeam(Some(Team::Red)));
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    let health = server.health(1).unwrap();
+    assert_eq!(health.current, health.max);
+}
+
+#[test]
+fn team_deathmatch_kills_score_for_the_team() {
+    let config = ServerConfig {
+        mode: GameModeKind::TeamDeathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+    assert_eq!(server.score(0), Some(1));
+
+    let lobby = server.clients[0].lobby().unwrap();
+    let team = lobby.clients[&server.player_id(0)].team.unwrap();
+    assert_eq!(server.team_score(team), Some(1));
+}
+
+#[test]
+fn kills_are_credited_and_sent_to_the_clients() {
+    let config = ServerConfig {
+        mode: GameModeKind::Deathmatch,
+        ..Default::default()
+    };
+    let mut server = TestServer::with_config(2, config);
+    server.start_gameplay();
+
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(FIREBALL_COOLDOWN_TICKS);
+    server.hit_with_fireball(0, 1);
+
+    let caster = server.stats(0);
+    assert_eq!(caster.kills, 1);
+    assert_eq!(caster.shots_fired, 2);
+    assert_eq!(caster.hits, 2);
+    assert_eq!(caster.damage_dealt, 2);
+    assert_eq!(caster.accuracy(), 1.);
+    let target = server.stats(1);
+    assert_eq!(target.deaths, 1);
+    assert_eq!(target.kills, 0);
+
+    let kill = (server.player_id(1), Some(server.player_id(0)));
+    for client in server.clients.iter() {
+        let kills: Vec<_> = client
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessages::Kill { victim, killer } => Some((*victim, *killer)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kills, vec![kill]);
+    }
+}
+
+#[test]
+fn stats_are_sent_apart_from_the_score() {
+    let mut server = TestServer::new(2);
+    server.start_gameplay();
+    let score_updates = |server: &TestServer| {
+        server.clients[0]
+            .messages
+            .iter()
+            .filter(|message| matches!(message, ServerMessages::UpdateScore(_)))
+            .count()
+    };
+    let last_stats = |server: &TestServer| {
+        server.clients[0]
+            .messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                ServerMessages::UpdateStats(stats) => Some(stats.get(server.player_id(0))),
+                _ => None,
+            })
+    };
+    let score_updates_before = score_updates(&server);
+
+    // Shots and hits that do not kill leave the score as it is
+    server.hit_with_fireball(0, 1);
+    server.run_ticks(60);
+    assert_eq!(score_updates(&server), score_updates_before);
+    let stats = last_stats(&server).unwrap();
+    assert_eq!(stats.shots_fired, 1);
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats, server.stats(0));
+}
+
+#[test]
+fn clients_receive_the_gameplay_values_of_the_server() {
+    let mut server = TestServer::new(1);
+    assert_eq!(
+        server.clients[0].server_info().unwrap().gameplay_config,
+        GameplayConfig::default()
+    );
+
+    let gameplay = GameplayConfig {
+        jump_speed: 300.,
+        ..Default::default()
+    };
+    server
+        .game
+        .world
+        .run(|mut config: UniqueViewMut<GameplayConfig>| *config = gameplay.clone())
+        .unwrap();
+    server.tick();
+
+    let update = server.clients[0]
+        .messages
+        .iter()
+        .rev()
+        .find_map(|message| match message {
+            ServerMessages::UpdateGameplayConfig(gameplay_config) => Some(gameplay_config),
+            _ => None,
+        });
+    assert_eq!(update, Some(&gameplay));
+}
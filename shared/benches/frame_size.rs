@@ -0,0 +1,158 @@
+// Compares the size of the server frames in the bit-packed format with the bincode format,
+// fails when the bit-packed frames are not smaller.
+// Run with `cargo bench -p shared --bench frame_size`.
+use glam::vec2;
+use serde::Serialize;
+use shipyard::{EntitiesViewMut, EntityId, Get, View, ViewMut, World};
+
+use shared::{
+    animation::{AnimationController, AnimationEntity},
+    network::{register_components, NetworkState, ServerFrame},
+    player::Player,
+    projectile::{Projectile, ProjectileType},
+    Health, PlayerId, Transform,
+};
+
+const PLAYERS: usize = 8;
+const PROJECTILES: usize = 8;
+
+#[derive(Serialize)]
+struct BincodeComponent<S> {
+    bitmask: Vec<bool>,
+    values: Vec<S>,
+}
+
+#[derive(Serialize)]
+struct BincodeComponentBlock {
+    id: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct BincodeFrame {
+    frame: u64,
+    baseline: Option<u64>,
+    entities: Vec<EntityId>,
+    components: Vec<BincodeComponentBlock>,
+}
+
+fn bincode_block<T: 'static + Sync + Send + NetworkState>(
+    id: u8,
+    entities: &[EntityId],
+    world: &World,
+) -> BincodeComponentBlock {
+    let data = world
+        .run(|components: View<T>| {
+            let mut bitmask = vec![false; entities.len()];
+            let mut values = vec![];
+            for (i, entity_id) in entities.iter().enumerate() {
+                if let Ok(component) = (&components).get(*entity_id) {
+                    bitmask[i] = true;
+                    values.push(component.state());
+                }
+            }
+            bincode::serialize(&BincodeComponent { bitmask, values }).unwrap()
+        })
+        .unwrap();
+
+    BincodeComponentBlock { id, data }
+}
+
+fn bincode_frame(frame: u64, entities: &[EntityId], world: &World) -> Vec<u8> {
+    let frame = BincodeFrame {
+        frame,
+        baseline: None,
+        entities: entities.to_vec(),
+        components: vec![
+            bincode_block::<Player>(0, entities, world),
+            bincode_block::<Health>(1, entities, world),
+            bincode_block::<Projectile>(2, entities, world),
+            bincode_block::<Transform>(3, entities, world),
+            bincode_block::<AnimationController>(4, entities, world),
+        ],
+    };
+    bincode::serialize(&frame).unwrap()
+}
+
+fn create_world() -> (World, Vec<EntityId>) {
+    let world = World::new();
+    let entities = world
+        .run(
+            |mut entities: EntitiesViewMut,
+             mut players: ViewMut<Player>,
+             mut health: ViewMut<Health>,
+             mut projectiles: ViewMut<Projectile>,
+             mut transforms: ViewMut<Transform>,
+             mut animations: ViewMut<AnimationController>| {
+                let mut created = vec![];
+                for i in 0..PLAYERS {
+                    let mut player = Player::new(PlayerId(i as u32));
+                    player.direction = vec2(0.6, -0.8);
+                    let transform = Transform::new(vec2(24. * i as f32, 100.), 0.);
+                    let entity_id = entities.add_entity(
+                        (&mut players, &mut health, &mut transforms, &mut animations),
+                        (
+                            player,
+                            Health::new(2),
+                            transform,
+                            AnimationEntity::Player.new_animation_controller(),
+                        ),
+                    );
+                    created.push(entity_id);
+                }
+                for i in 0..PROJECTILES {
+                    let owner = created[i % PLAYERS];
+                    let projectile =
+                        Projectile::new(ProjectileType::Fireball, vec2(300., 0.), owner);
+                    let transform = Transform::new(vec2(40. * i as f32, 60.), 1.2);
+                    let entity_id = entities
+                        .add_entity((&mut projectiles, &mut transforms), (projectile, transform));
+                    created.push(entity_id);
+                }
+                created
+            },
+        )
+        .unwrap();
+
+    (world, entities)
+}
+
+fn move_entities(world: &World) {
+    world
+        .run(|mut transforms: ViewMut<Transform>| {
+            for transform in (&mut transforms).iter() {
+                transform.position.x += 2.5;
+            }
+        })
+        .unwrap();
+}
+
+fn main() {
+    let registry = register_components();
+
+    let (world, entities) = create_world();
+    let baseline = ServerFrame::from_world(1, &world, &registry);
+    let bincode_baseline = bincode_frame(1, &entities, &world);
+
+    move_entities(&world);
+    let frame = ServerFrame::from_world(2, &world, &registry);
+    let delta = frame.delta(&baseline, &registry);
+
+    let bincode_size = bincode_baseline.len();
+    let full_size = baseline.to_bytes().len();
+    let delta_size = delta.to_bytes().len();
+    println!("{} players and {} projectiles", PLAYERS, PROJECTILES);
+    println!("full frame, bincode:     {} bytes", bincode_size);
+    println!("full frame, bit-packed:  {} bytes", full_size);
+    println!("delta frame, bit-packed: {} bytes", delta_size);
+
+    assert!(
+        full_size < bincode_size,
+        "bit-packed frames are not smaller than bincode"
+    );
+    // Only the moved positions are sent against the baseline
+    assert!(
+        delta_size < full_size,
+        "delta frames are not smaller than full frames"
+    );
+}
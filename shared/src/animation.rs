@@ -33,7 +33,7 @@ pub struct AnimationController {
     last_updated: Instant,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum AnimationEntity {
     Player,
 }
@@ -53,7 +53,7 @@ impl AnimationEntity {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AnimationState {
     pub animation_entity: AnimationEntity,
     pub frame: u8,
@@ -62,6 +62,8 @@ pub struct AnimationState {
 
 impl NetworkState for AnimationController {
     type State = AnimationState;
+    // The state fits in a few bytes, it is sent whole
+    type Delta = AnimationState;
 
     fn from_state(state: Self::State) -> Self {
         let mut animation_controller = state.animation_entity.new_animation_controller();
@@ -81,6 +83,17 @@ impl NetworkState for AnimationController {
             frame: self.frame as u8,
         }
     }
+
+    fn delta(state: &AnimationState, _baseline: Option<&AnimationState>) -> AnimationState {
+        state.clone()
+    }
+
+    fn apply_delta(
+        _baseline: Option<&AnimationState>,
+        delta: AnimationState,
+    ) -> Option<AnimationState> {
+        Some(delta)
+    }
 }
 
 impl AnimationController {
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::message::{decode_message, PROTOCOL_VERSION};
+
+/// Port the servers listen on for discovery queries broadcast in the local network.
+pub const DISCOVERY_PORT: u16 = 5050;
+// Sent in every discovery message, other packets on the port are ignored.
+const MAGIC: [u8; 4] = *b"WZFL";
+// Servers not answering for this long are removed from the list.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+const QUERY_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DiscoveryQuery {
+    magic: [u8; 4],
+}
+
+/// Answer of a server to a discovery query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerAnnouncement {
+    pub protocol_version: u32,
+    pub name: String,
+    // Port of the game, the address is the one the answer came from.
+    pub port: u16,
+    pub level: String,
+    pub mode: String,
+    pub players: u32,
+    pub max_players: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DiscoveryAnswer {
+    magic: [u8; 4],
+    announcement: ServerAnnouncement,
+}
+
+/// Answers the discovery queries of the clients in the local network.
+pub struct DiscoveryResponder {
+    socket: UdpSocket,
+}
+
+impl DiscoveryResponder {
+    pub fn new() -> Result<Self, io::Error> {
+        Self::bind(DISCOVERY_PORT)
+    }
+
+    /// Answers the queries sent to the given port instead, zero picks a free one.
+    pub fn bind(port: u16) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.socket.local_addr()
+    }
+
+    pub fn update(&mut self, announcement: &ServerAnnouncement) {
+        let mut buffer = [0u8; 64];
+        while let Ok((len, addr)) = self.socket.recv_from(&mut buffer) {
+            match decode_message::<DiscoveryQuery>(&buffer[..len]) {
+                Ok(query) if query.magic == MAGIC => {}
+                _ => continue,
+            }
+            let answer = DiscoveryAnswer {
+                magic: MAGIC,
+                announcement: announcement.clone(),
+            };
+            let answer = bincode::serialize(&answer).unwrap();
+            // The client may be gone already, it queries again anyway
+            let _ = self.socket.send_to(&answer, addr);
+        }
+    }
+}
+
+/// Server found in the local network.
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    pub addr: SocketAddr,
+    pub announcement: ServerAnnouncement,
+}
+
+impl DiscoveredServer {
+    pub fn compatible(&self) -> bool {
+        self.announcement.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+/// Broadcasts discovery queries and collects the answers of the servers.
+pub struct LanBrowser {
+    socket: UdpSocket,
+    // Where the queries are sent, the broadcast address of the discovery port.
+    query_addr: SocketAddr,
+    servers: HashMap<SocketAddr, (ServerAnnouncement, Instant)>,
+    last_query: Option<Instant>,
+}
+
+impl LanBrowser {
+    pub fn new() -> Result<Self, io::Error> {
+        Self::with_query_addr(SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)))
+    }
+
+    /// Sends the queries to the given address instead of broadcasting them.
+    pub fn with_query_addr(query_addr: SocketAddr) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            query_addr,
+            servers: HashMap::new(),
+            last_query: None,
+        })
+    }
+
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let query_due = self
+            .last_query
+            .map_or(true, |last_query| now - last_query >= QUERY_INTERVAL);
+        if query_due {
+            self.last_query = Some(now);
+            let query = bincode::serialize(&DiscoveryQuery { magic: MAGIC }).unwrap();
+            if let Err(e) = self.socket.send_to(&query, self.query_addr) {
+                debug!("Failed to send discovery query: {}", e);
+            }
+        }
+
+        let mut buffer = [0u8; 1024];
+        while let Ok((len, addr)) = self.socket.recv_from(&mut buffer) {
+            let answer = match decode_message::<DiscoveryAnswer>(&buffer[..len]) {
+                Ok(answer) if answer.magic == MAGIC => answer,
+                _ => continue,
+            };
+            let server_addr = SocketAddr::new(addr.ip(), answer.announcement.port);
+            self.servers.insert(server_addr, (answer.announcement, now));
+        }
+
+        self.servers
+            .retain(|_, (_, last_seen)| now - *last_seen < SERVER_TIMEOUT);
+    }
+
+    /// Servers that answered recently, sorted by address.
+    pub fn servers(&self) -> Vec<DiscoveredServer> {
+        let mut servers: Vec<DiscoveredServer> = self
+            .servers
+            .iter()
+            .map(|(addr, (announcement, _))| DiscoveredServer {
+                addr: *addr,
+                announcement: announcement.clone(),
+            })
+            .collect();
+        servers.sort_by_key(|server| server.addr);
+        servers
+    }
+}
@@ -1,23 +1,179 @@
 use ldtk_rust::Project;
-use macroquad::prelude::*;
-use shipyard::World;
+use std::fmt;
+use std::fs;
+use std::io;
+use glam::{vec2, Vec2};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use shipyard::{UniqueViewMut, World};
 
+use crate::math::Rect;
 use crate::physics::Physics;
+use crate::Team;
 
 pub const BASE_DIR: &str = "../levels/";
 pub const PROJECT_FILE: &str = "Typical_TopDown_example.ldtk";
+pub const DEFAULT_LEVEL: &str = "First";
+// Color of the collision tiles in the physics debug rendering.
+const COLLISIONS_DEBUG_COLOR: [f32; 4] = [0.0, 0.89, 0.19, 1.0];
 
 pub fn load_project() -> Project {
     Project::new(BASE_DIR.to_owned() + PROJECT_FILE)
 }
 
+/// Identifiers of the levels in the LDtk project.
+pub fn level_identifiers() -> Vec<String> {
+    load_project()
+        .levels
+        .into_iter()
+        .map(|level| level.identifier)
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct ProjectFiles {
+    levels: Vec<LevelFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LevelFile {
+    // Set when the level is saved in its own file.
+    external_rel_path: Option<String>,
+}
+
+/// Paths of the project file and of the level files it references.
+pub fn level_files() -> Result<Vec<String>, io::Error> {
+    let project_path = BASE_DIR.to_owned() + PROJECT_FILE;
+    let project = fs::read(&project_path)?;
+    let project: ProjectFiles = serde_json::from_slice(&project)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut files = vec![project_path];
+    for level in project.levels {
+        if let Some(path) = level.external_rel_path {
+            files.push(BASE_DIR.to_owned() + &path);
+        }
+    }
+    Ok(files)
+}
+
+/// How the server picks the level of the next round from its pool of levels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LevelRotation {
+    // Always the same level.
+    Fixed,
+    // The levels in the order of the pool.
+    Sequential,
+    Random,
+    // The level with most votes of the players, random in a tie.
+    Vote,
+}
+
+impl LevelRotation {
+    pub const ALL: [LevelRotation; 4] = [
+        LevelRotation::Fixed,
+        LevelRotation::Sequential,
+        LevelRotation::Random,
+        LevelRotation::Vote,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LevelRotation::Fixed => "fixed",
+            LevelRotation::Sequential => "sequential",
+            LevelRotation::Random => "random",
+            LevelRotation::Vote => "vote",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|rotation| rotation.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RespawnPoint {
+    pub position: Vec2,
+    // From the `Team` field of the entity, points without a team are used by everyone.
+    pub team: Option<Team>,
+}
+
+pub struct PlayerRespawnPoints(pub Vec<RespawnPoint>);
+
+impl PlayerRespawnPoints {
+    /// Positions a player of the team can spawn at, the ones of the other team are left out.
+    pub fn positions(&self, team: Option<Team>) -> Vec<Vec2> {
+        let positions: Vec<Vec2> = self
+            .0
+            .iter()
+            .filter(|point| team.is_none() || point.team.is_none() || point.team == team)
+            .map(|point| point.position)
+            .collect();
+        // Levels may only have points for the other team
+        if positions.is_empty() {
+            self.0.iter().map(|point| point.position).collect()
+        } else {
+            positions
+        }
+    }
+}
+
+// Area marked with a `Hill` entity, levels without one can not be played in king of the hill.
+pub struct LevelHill(pub Option<Rect>);
+
+/// Identifier of a level that is not in the LDtk project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelNotFound(pub String);
+
+impl fmt::Display for LevelNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "level {} not found in the LDtk project", self.0)
+    }
+}
+
+impl std::error::Error for LevelNotFound {}
+
+pub fn load_level_collisions(world: &mut World, level: &str) -> Result<(), LevelNotFound> {
+    let (physics, player_respawn_points, hill) = level_collisions(level)?;
+    world.add_unique(player_respawn_points).unwrap();
+    world.add_unique(hill).unwrap();
+    world.add_unique(physics).unwrap();
+    Ok(())
+}
 
-pub struct PlayerRespawnPoints(pub Vec<Vec2>);
+/// Replaces the collisions loaded in the world with the ones of another level,
+/// the world is left as it is when the level is not found.
+pub fn replace_level_collisions(world: &World, level: &str) -> Result<(), LevelNotFound> {
+    let (new_physics, new_respawn_points, new_hill) = level_collisions(level)?;
+    world
+        .run(
+            |mut physics: UniqueViewMut<Physics>,
+             mut player_respawn_points: UniqueViewMut<PlayerRespawnPoints>,
+             mut hill: UniqueViewMut<LevelHill>| {
+                *physics = new_physics;
+                *player_respawn_points = new_respawn_points;
+                *hill = new_hill;
+            },
+        )
+        .unwrap();
+    Ok(())
+}
 
-pub fn load_level_collisions(world: &mut World) {
+fn level_collisions(
+    level: &str,
+) -> Result<(Physics, PlayerRespawnPoints, LevelHill), LevelNotFound> {
     let project = load_project();
+    let level = project
+        .levels
+        .iter()
+        .find(|l| l.identifier == level)
+        .ok_or_else(|| LevelNotFound(level.to_owned()))?;
 
-    let entity_layer = project.levels[0]
+    let entity_layer = level
         .layer_instances
         .as_ref()
         .unwrap()
@@ -26,20 +182,47 @@ pub fn load_level_collisions(world: &mut World) {
         .unwrap();
 
     let mut player_respawn_points = PlayerRespawnPoints(vec![]);
+    let mut hill = LevelHill(None);
 
     for entity in entity_layer.entity_instances.iter() {
-        println!("Entity identifier: {}", entity.identifier);
-        println!("Entity px: {:?}", entity.px);
-        player_respawn_points
-            .0
-            .push(vec2(entity.px[0] as f32, entity.px[1] as f32));
+        debug!("Entity identifier: {}", entity.identifier);
+        debug!("Entity px: {:?}", entity.px);
+        let px = vec2(entity.px[0] as f32, entity.px[1] as f32);
+        match entity.identifier.as_str() {
+            "PlayerRespawn" => {
+                let team = entity
+                    .field_instances
+                    .iter()
+                    .find(|field| field.identifier == "Team")
+                    .and_then(|field| field.value.as_ref())
+                    .and_then(|value| value.as_str())
+                    .and_then(Team::from_name);
+                player_respawn_points
+                    .0
+                    .push(RespawnPoint { position: px, team });
+            }
+            "Hill" => {
+                // The size is the one of the entity definition, the position is its pivot
+                let definition = project
+                    .defs
+                    .as_ref()
+                    .unwrap()
+                    .entities
+                    .iter()
+                    .find(|definition| definition.identifier == entity.identifier)
+                    .unwrap();
+                let size = vec2(definition.width as f32, definition.height as f32);
+                let pivot = vec2(entity.pivot[0] as f32, entity.pivot[1] as f32);
+                let position = px - pivot * size;
+                hill.0 = Some(Rect::new(position.x, position.y, size.x, size.y));
+            }
+            _ => {}
+        }
     }
 
-    world.add_unique(player_respawn_points).unwrap();
-
     let mut physics: Physics = Physics::new();
 
-    let collision_layer = project.levels[0]
+    let collision_layer = level
         .layer_instances
         .as_ref()
         .unwrap()
@@ -66,9 +249,9 @@ pub fn load_level_collisions(world: &mut World) {
         grid_size.y,
         grid_width,
         1,
-        GREEN,
+        COLLISIONS_DEBUG_COLOR,
     );
 
-    world.add_unique(physics).unwrap();
+    Ok((physics, player_respawn_points, hill))
 }
 
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use std::{collections::HashMap, fmt, time::Duration};
 
 use glam::{vec2, Vec2};
 use serde::{Deserialize, Serialize};
@@ -8,24 +8,69 @@ use renet_udp::renet::channel::{ChannelConfig, ReliableChannelConfig, Unreliable
 use derive::NetworkState;
 
 pub mod animation;
+pub mod discovery;
 pub mod ldtk;
 pub mod message;
 pub mod network;
 pub mod player;
 pub mod projectile;
 pub mod timer;
+pub mod transport;
 pub mod physics;
 pub mod math;
 
 // Server EntityId -> Client EntityId
 pub type EntityMapping = HashMap<EntityId, EntityId>;
 
-#[derive(Debug, Clone, Serialize, Deserialize, NetworkState)]
+/// Identity of a player, assigned by the server when the client connects.
+/// Unlike the client address it does not change with the connection.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize,
+)]
+pub struct PlayerId(pub u32);
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Secret handed to each client, used to resume the player after reconnecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionToken(pub u64);
+
+/// Simulation steps per second of the server, the client prediction runs at the same rate.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TickRate(pub u32);
+
+impl Default for TickRate {
+    fn default() -> Self {
+        Self(60)
+    }
+}
+
+impl TickRate {
+    /// Duration of a simulation step in seconds.
+    pub fn frame_time(&self) -> f32 {
+        1. / self.0 as f32
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(1. / self.0 as f64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, NetworkState)]
 pub struct Transform {
+    #[network(with = crate::network::bits::position)]
     pub position: Vec2,
+    #[network(with = crate::network::bits::angle)]
     pub rotation: f32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Channel {
     Reliable = 0,
@@ -73,31 +118,257 @@ impl Transform {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientInfo {
+    pub name: String,
     pub ready: bool,
+    // False while the server waits for the player to reconnect.
+    pub connected: bool,
+    // Level voted for the next round.
+    pub level_vote: Option<String>,
+    // Game mode voted for the next match.
+    pub mode_vote: Option<GameModeKind>,
+    // Team picked in the lobby, none to be placed in the smallest team.
+    pub team_pick: Option<Team>,
+    // Team in the current or last match of a team mode.
+    pub team: Option<Team>,
 }
 
-impl Default for ClientInfo {
-    fn default() -> Self {
-        Self { ready: false }
+impl ClientInfo {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            ready: false,
+            connected: true,
+            level_vote: None,
+            mode_vote: None,
+            team_pick: None,
+            team: None,
+        }
     }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LobbyInfo {
-    pub clients: HashMap<SocketAddr, ClientInfo>,
+    pub clients: HashMap<PlayerId, ClientInfo>,
+    // Mode of the current or last match.
+    pub mode: GameModeKind,
+}
+
+impl LobbyInfo {
+    /// Display name of the player, players that already left are shown by id.
+    pub fn player_name(&self, player_id: PlayerId) -> String {
+        match self.clients.get(&player_id) {
+            Some(client_info) => client_info.name.clone(),
+            None => player_id.to_string(),
+        }
+    }
+
+    pub fn team(&self, player_id: PlayerId) -> Option<Team> {
+        self.clients
+            .get(&player_id)
+            .and_then(|client_info| client_info.team)
+    }
+}
+
+/// How the players score and win a match, implemented by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum GameModeKind {
+    // The last player alive wins the round.
+    LastWizardStanding,
+    // Kills score and the players respawn.
+    Deathmatch,
+    TeamDeathmatch,
+    // Players score while they hold the hill of the level alone.
+    KingOfTheHill,
+}
+
+impl Default for GameModeKind {
+    fn default() -> Self {
+        GameModeKind::LastWizardStanding
+    }
+}
+
+impl GameModeKind {
+    pub const ALL: [GameModeKind; 4] = [
+        GameModeKind::LastWizardStanding,
+        GameModeKind::Deathmatch,
+        GameModeKind::TeamDeathmatch,
+        GameModeKind::KingOfTheHill,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameModeKind::LastWizardStanding => "last-wizard-standing",
+            GameModeKind::Deathmatch => "deathmatch",
+            GameModeKind::TeamDeathmatch => "team-deathmatch",
+            GameModeKind::KingOfTheHill => "king-of-the-hill",
+        }
+    }
+
+    /// Name shown to the players.
+    pub fn title(&self) -> &'static str {
+        match self {
+            GameModeKind::LastWizardStanding => "Last wizard standing",
+            GameModeKind::Deathmatch => "Deathmatch",
+            GameModeKind::TeamDeathmatch => "Team deathmatch",
+            GameModeKind::KingOfTheHill => "King of the hill",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+
+    pub fn has_teams(&self) -> bool {
+        *self == GameModeKind::TeamDeathmatch
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Team {
+    Red,
+    Blue,
+}
+
+impl Team {
+    pub const ALL: [Team; 2] = [Team::Red, Team::Blue];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Team::Red => "red",
+            Team::Blue => "blue",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::ALL.iter().copied().find(|team| team.name() == name)
+    }
+}
+
+/// What a player did in the current match.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub kills: u32,
+    pub deaths: u32,
+    pub damage_dealt: u32,
+    pub shots_fired: u32,
+    // Fireballs that hit a player.
+    pub hits: u32,
+}
+
+impl PlayerStats {
+    /// Kills per death, the kills when the player never died.
+    pub fn kill_death_ratio(&self) -> f32 {
+        self.kills as f32 / self.deaths.max(1) as f32
+    }
+
+    /// Fraction of the fireballs that hit a player.
+    pub fn accuracy(&self) -> f32 {
+        if self.shots_fired == 0 {
+            return 0.;
+        }
+        (self.hits as f32 / self.shots_fired as f32).min(1.)
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct PlayersScore {
-    pub score: HashMap<SocketAddr, u8>,
+    pub score: HashMap<PlayerId, u8>,
+    // Sum of the points of each team in the team modes, empty otherwise.
+    pub team_score: HashMap<Team, u8>,
     pub updated: bool
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, NetworkState)]
+/// Stats of the players in the match, they change with every fireball so
+/// the clients are sent them less often than the score.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct PlayersStats {
+    pub stats: HashMap<PlayerId, PlayerStats>,
+    pub updated: bool,
+}
+
+impl PlayersStats {
+    pub fn get(&self, player_id: PlayerId) -> PlayerStats {
+        self.stats.get(&player_id).copied().unwrap_or_default()
+    }
+
+    /// Stats of the player to be changed, the clients get them in the next stats update.
+    pub fn get_mut(&mut self, player_id: PlayerId) -> &mut PlayerStats {
+        self.updated = true;
+        self.stats.entry(player_id).or_default()
+    }
+}
+
+impl PlayersScore {
+    /// Best score of the teams in the team modes, otherwise of the players.
+    pub fn leading_score(&self) -> u8 {
+        let best = if self.team_score.is_empty() {
+            self.score.values().max()
+        } else {
+            self.team_score.values().max()
+        };
+        best.copied().unwrap_or(0)
+    }
+}
+
+/// When a match ends, a limit of zero is disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MatchRules {
+    // Rounds a player must win.
+    pub score_limit: u8,
+    // Rounds played before the match ends.
+    pub max_rounds: u32,
+    // Seconds of gameplay before the match ends, the running round is not scored.
+    pub time_limit: u32,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            score_limit: 5,
+            max_rounds: 0,
+            time_limit: 0,
+        }
+    }
+}
+
+impl MatchRules {
+    pub fn is_over(&self, players_score: &PlayersScore, rounds: u32, time: Duration) -> bool {
+        let score_reached =
+            self.score_limit > 0 && players_score.leading_score() >= self.score_limit;
+        let rounds_reached = self.max_rounds > 0 && rounds >= self.max_rounds;
+        let time_reached =
+            self.time_limit > 0 && time >= Duration::from_secs(self.time_limit as u64);
+        score_reached || rounds_reached || time_reached
+    }
+
+    /// Short description shown in the lobby.
+    pub fn describe(&self) -> String {
+        let mut rules = vec![];
+        if self.score_limit > 0 {
+            rules.push(format!("first to {} wins", self.score_limit));
+        }
+        if self.max_rounds > 0 {
+            rules.push(format!("{} rounds", self.max_rounds));
+        }
+        if self.time_limit > 0 {
+            let (minutes, seconds) = (self.time_limit / 60, self.time_limit % 60);
+            rules.push(format!("time limit {}:{:02}", minutes, seconds));
+        }
+        if rules.is_empty() {
+            "endless match".to_string()
+        } else {
+            rules.join(", ")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, NetworkState)]
 pub struct Health {
     pub max: u8,
     pub current: u8,
-    pub killer: Option<SocketAddr>,
+    pub killer: Option<PlayerId>,
 }
 
 impl Health {
@@ -109,7 +380,7 @@ impl Health {
         }
     }
 
-    pub fn take_damage(&mut self, damage: u8, damage_dealer: Option<SocketAddr>) {
+    pub fn take_damage(&mut self, damage: u8, damage_dealer: Option<PlayerId>) {
         if self.is_dead() {
             return;
         }
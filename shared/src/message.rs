@@ -1,7 +1,21 @@
-use serde::{Deserialize, Serialize};
-use crate::player::PlayerInput;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use bincode::Options;
+use crate::player::{GameplayConfig, PlayerInput};
+use crate::ldtk::{level_files, LevelRotation};
 use crate::network::ServerFrame;
-use crate::{PlayersScore, LobbyInfo};
+use crate::{
+    GameModeKind, LobbyInfo, MatchRules, PlayerId, PlayerStats, PlayersScore, PlayersStats,
+    SessionToken, Team, TickRate,
+};
+use std::fmt;
+use std::fs;
+use std::io;
+
+// Increased with every change to the messages, clients and servers must use the same version.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+// Largest message accepted from the network, bigger ones are malformed or hostile.
+pub const MAX_MESSAGE_SIZE: u64 = 16 * 1024;
 
 pub enum Messages {
     PlayerInput(PlayerInput),
@@ -10,13 +24,183 @@ pub enum Messages {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ServerMessages {
+    ServerInfo(ServerInfo),
     UpdateScore(PlayersScore),
+    // Sent at most once per second while the stats change, and when a round ends.
+    UpdateStats(PlayersStats),
     UpdateLobby(LobbyInfo),
     StartGameplay,
+    // Identifier of the level played from the next round on.
+    ChangeLevel(String),
+    // Gameplay values changed in the server, the clients predict their player with them.
+    UpdateGameplayConfig(GameplayConfig),
+    // The client is disconnected after this message.
+    Kicked {
+        reason: String,
+    },
+    Shutdown,
+    // Final standings, the best first. The players are back in the lobby.
+    MatchOver {
+        standings: Vec<Standing>,
+        // Empty unless the match was played in teams.
+        team_scores: Vec<(Team, u8)>,
+    },
+    // A player died in the running round, shown in the kill feed.
+    Kill {
+        victim: PlayerId,
+        // None when the caster of the fireball already left.
+        killer: Option<PlayerId>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Scene {
+    Lobby,
+    Gameplay,
+}
+
+// Sent to each client when it connects, clients joining a match
+// already running go straight to the gameplay.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    // Identity assigned to the client.
+    pub player_id: PlayerId,
+    // Sent back when reconnecting to keep the same player.
+    pub session_token: SessionToken,
+    pub tick_rate: TickRate,
+    pub scene: Scene,
+    // Identifier of the LDtk level being played.
+    pub level: String,
+    // Levels the server picks from between rounds.
+    pub levels: Vec<String>,
+    pub level_rotation: LevelRotation,
+    pub rules: MatchRules,
+    pub score: PlayersScore,
+    pub stats: PlayersStats,
+    pub gameplay_config: GameplayConfig,
+}
+
+/// Result of a player at the end of a match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Standing {
+    pub player_id: PlayerId,
+    // Kept in case the player leaves while the results are shown.
+    pub name: String,
+    pub team: Option<Team>,
+    pub score: u8,
+    pub stats: PlayerStats,
+}
+
+/// First message sent by the client. Its layout must stay the same in every
+/// version, so mismatched builds can tell why they can not play together.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub protocol_version: u32,
+    // Hash of the levels and the gameplay values, see `content_hash`.
+    pub content_hash: u64,
+}
+
+impl Handshake {
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(Self {
+            protocol_version: PROTOCOL_VERSION,
+            content_hash: content_hash()?,
+        })
+    }
+}
+
+/// First message sent by the server, a rejected client is disconnected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HandshakeResponse {
+    Accepted,
+    Rejected(String),
+}
+
+/// Players of a match must have the same levels and default gameplay values,
+/// otherwise the prediction and the collisions differ from the server.
+/// Values changed while the server runs are sent to the clients instead.
+/// Fails when a file of the levels can not be read.
+pub fn content_hash() -> Result<u64, io::Error> {
+    let mut data = vec![];
+    for path in level_files()? {
+        let file = fs::read(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to read {}: {}", path, e)))?;
+        data.push(file);
+    }
+    data.push(bincode::serialize(&GameplayConfig::default()).unwrap());
+    let data: Vec<&[u8]> = data.iter().map(|bytes| bytes.as_slice()).collect();
+    Ok(fnv1a(&data))
+}
+
+// Unlike the std hasher the result is the same in every build and platform.
+fn fnv1a(data: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data.iter().flat_map(|bytes| bytes.iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// Sent unreliably by the client for every frame received,
+// the server encodes the next frames against the last one acknowledged.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FrameAck {
+    pub frame: u64,
+}
+
+// Sent by the client when it connects.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub name: String,
+    // Session of a previous connection, to resume its player.
+    pub session_token: Option<SessionToken>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientAction {
+    Join(JoinRequest),
     LobbyReady,
+    // Level the player wants for the next round, when the server rotates levels by vote.
+    VoteLevel(String),
+    // Mode the player wants for the next match.
+    VoteMode(GameModeKind),
+    // Team for the next matches played in teams, none to be balanced by the server.
+    PickTeam(Option<Team>),
+    // Frees the player right away instead of holding it for a reconnect.
+    Leave,
 }
 
+#[derive(Debug)]
+pub enum MessageError {
+    Decode(bincode::Error),
+    InvalidDirection,
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageError::Decode(e) => write!(f, "failed to decode message: {}", e),
+            MessageError::InvalidDirection => write!(f, "input direction is not normalized"),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+impl From<bincode::Error> for MessageError {
+    fn from(e: bincode::Error) -> Self {
+        MessageError::Decode(e)
+    }
+}
+
+/// Decodes a message received from the network, the same encoding as `bincode::serialize`
+/// but bounded by `MAX_MESSAGE_SIZE` so a bad length can not allocate too much memory.
+pub fn decode_message<T: DeserializeOwned>(data: &[u8]) -> Result<T, MessageError> {
+    let message = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+        .deserialize(data)?;
+    Ok(message)
+}
@@ -1,57 +1,294 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use glam::{vec2, Vec2};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use shipyard::{
-    AllStoragesViewMut, EntitiesView, EntitiesViewMut, EntityId, Get, IntoIter, IntoWithId,
+    AllStoragesViewMut, EntitiesView, EntitiesViewMut, EntityId, Get, IntoIter, IntoWithId, Remove,
     UniqueViewMut, View, ViewMut, World,
 };
 
-use crate::{
-    Transform, EntityMapping, Health,
-    player::Player,
-    projectile::Projectile,
-    animation::AnimationController
-};
+use crate::animation::AnimationController;
+use crate::player::Player;
+use crate::projectile::Projectile;
+use crate::{EntityMapping, Health, Transform};
+
+use self::bits::{deserialize, serialize};
+
+pub mod bits;
 
 pub trait NetworkState {
-    type State: Clone + std::fmt::Debug + Serialize + DeserializeOwned;
+    type State: Clone + PartialEq + std::fmt::Debug + Serialize + DeserializeOwned;
+    /// Part of the state sent in delta frames, the derive only keeps the fields that changed.
+    type Delta: Clone + PartialEq + std::fmt::Debug + Serialize + DeserializeOwned;
 
     fn from_state(state: Self::State) -> Self;
     fn update_from_state(&mut self, state: Self::State);
     fn state(&self) -> Self::State;
+
+    /// Encodes the state against the one of the baseline, the whole state without baseline.
+    fn delta(state: &Self::State, baseline: Option<&Self::State>) -> Self::Delta;
+    /// Returns None when the delta needs a baseline that is missing.
+    fn apply_delta(baseline: Option<&Self::State>, delta: Self::Delta) -> Option<Self::State>;
+}
+
+/// Lossy encoding used by `#[network(quantize = step)]`, values are rounded to the step.
+pub trait Quantize {
+    type Quantized: Clone + PartialEq + std::fmt::Debug + Serialize + DeserializeOwned;
+
+    fn quantize(&self, step: f32) -> Self::Quantized;
+    fn dequantize(quantized: Self::Quantized, step: f32) -> Self;
+}
+
+impl Quantize for f32 {
+    type Quantized = i32;
+
+    fn quantize(&self, step: f32) -> i32 {
+        (self / step).round() as i32
+    }
+
+    fn dequantize(quantized: i32, step: f32) -> Self {
+        quantized as f32 * step
+    }
+}
+
+impl Quantize for Vec2 {
+    type Quantized = (i32, i32);
+
+    fn quantize(&self, step: f32) -> (i32, i32) {
+        (self.x.quantize(step), self.y.quantize(step))
+    }
+
+    fn dequantize(quantized: (i32, i32), step: f32) -> Self {
+        vec2(
+            f32::dequantize(quantized.0, step),
+            f32::dequantize(quantized.1, step),
+        )
+    }
+}
+
+// Amount of frames kept as possible delta baselines.
+pub const FRAME_HISTORY_SIZE: usize = 32;
+
+// Stable id of a replicated component, must be the same on server and client.
+pub type ComponentId = u8;
+
+#[derive(Debug)]
+pub enum FrameError {
+    UnknownComponent(ComponentId),
+    Decode(bits::Error),
+    InvalidDelta,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::UnknownComponent(id) => write!(f, "unknown network component {}", id),
+            FrameError::Decode(e) => write!(f, "failed to decode network component: {}", e),
+            FrameError::InvalidDelta => write!(f, "network component delta without its baseline"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<bits::Error> for FrameError {
+    fn from(e: bits::Error) -> Self {
+        FrameError::Decode(e)
+    }
+}
+
+type FromWorldFn = fn(&[EntityId], &World) -> Vec<u8>;
+type ApplyInWorldFn = fn(&[u8], &[EntityId], &World) -> Result<(), FrameError>;
+// Returns None when no component changed since the baseline.
+type DeltaFn =
+    fn(&[u8], &[EntityId], Option<&[u8]>, &[EntityId]) -> Result<Option<Vec<u8>>, FrameError>;
+type DecodeDeltaFn =
+    fn(Option<&[u8]>, &[EntityId], Option<&[u8]>, &[EntityId]) -> Result<Vec<u8>, FrameError>;
+
+struct RegisteredComponent {
+    id: ComponentId,
+    from_world: FromWorldFn,
+    apply_in_world: ApplyInWorldFn,
+    delta: DeltaFn,
+    decode_delta: DecodeDeltaFn,
+}
+
+/// Components replicated from the server to the clients.
+/// Server and client must register the same components with the same ids.
+#[derive(Default)]
+pub struct NetworkRegistry {
+    components: Vec<RegisteredComponent>,
+}
+
+impl NetworkRegistry {
+    pub fn register<T: 'static + Sync + Send + Clone + NetworkState>(
+        &mut self,
+        id: ComponentId,
+    ) -> &mut Self {
+        assert!(
+            self.get(id).is_none(),
+            "Network component id {} is already registered.",
+            id
+        );
+
+        self.components.push(RegisteredComponent {
+            id,
+            from_world: NetworkComponent::<T>::serialized_from_world,
+            apply_in_world: NetworkComponent::<T>::apply_serialized_in_world,
+            delta: NetworkComponent::<T>::serialized_delta,
+            decode_delta: NetworkComponent::<T>::serialized_decode_delta,
+        });
+        self
+    }
+
+    fn get(&self, id: ComponentId) -> Option<&RegisteredComponent> {
+        self.components.iter().find(|c| c.id == id)
+    }
+}
+
+/// Registry with the replicated components of the game, the same for the server and the clients.
+pub fn register_components() -> NetworkRegistry {
+    let mut registry = NetworkRegistry::default();
+    registry
+        .register::<Player>(0)
+        .register::<Health>(1)
+        .register::<Projectile>(2)
+        .register::<Transform>(3)
+        .register::<AnimationController>(4);
+    registry
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ComponentBlock {
+    id: ComponentId,
+    data: Vec<u8>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerFrame {
+    pub frame: u64,
+    // When present, only the components that changed since this frame are sent.
+    pub baseline: Option<u64>,
     entities: Vec<EntityId>,
-    players: NetworkComponent<Player>,
-    health: NetworkComponent<Health>,
-    projectiles: NetworkComponent<Projectile>,
-    transforms: NetworkComponent<Transform>,
-    animations: NetworkComponent<AnimationController>,
+    components: Vec<ComponentBlock>,
 }
 
 impl ServerFrame {
-    pub fn from_world(world: &World) -> Self {
+    pub fn from_world(frame: u64, world: &World, registry: &NetworkRegistry) -> Self {
         let entities: Vec<EntityId> = world
             .run(|entities: EntitiesView| entities.iter().collect())
             .unwrap();
 
+        let components = registry
+            .components
+            .iter()
+            .map(|component| ComponentBlock {
+                id: component.id,
+                data: (component.from_world)(&entities, world),
+            })
+            .collect();
+
         Self {
-            players: NetworkComponent::<Player>::from_world(&entities, world),
-            projectiles: NetworkComponent::<Projectile>::from_world(&entities, world),
-            transforms: NetworkComponent::<Transform>::from_world(&entities, world),
-            animations: NetworkComponent::<AnimationController>::from_world(&entities, world),
-            health: NetworkComponent::<Health>::from_world(&entities, world),
+            frame,
+            baseline: None,
             entities,
+            components,
         }
     }
 
-    pub fn apply_in_world(&self, world: &World) {
-        self.players.apply_in_world(&self.entities, world);
-        self.projectiles.apply_in_world(&self.entities, world);
-        self.transforms.apply_in_world(&self.entities, world);
-        self.animations.apply_in_world(&self.entities, world);
-        self.health.apply_in_world(&self.entities, world);
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serialize(self).expect("Failed to serialize server frame.")
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, FrameError> {
+        Ok(deserialize(data)?)
+    }
+
+    fn block(&self, id: ComponentId) -> Option<&[u8]> {
+        self.components
+            .iter()
+            .find(|block| block.id == id)
+            .map(|block| &block.data[..])
+    }
+
+    /// Encode this frame against a baseline the client already has,
+    /// only components that changed or were removed since the baseline are kept.
+    pub fn delta(&self, baseline: &ServerFrame, registry: &NetworkRegistry) -> ServerFrame {
+        let mut components = vec![];
+        for block in self.components.iter() {
+            let component = registry
+                .get(block.id)
+                .expect("Server frame component is not registered.");
+            let data = (component.delta)(
+                &block.data,
+                &self.entities,
+                baseline.block(block.id),
+                &baseline.entities,
+            )
+            .expect("Server frame components are always valid.");
 
+            // Unchanged components are not sent
+            if let Some(data) = data {
+                components.push(ComponentBlock { id: block.id, data });
+            }
+        }
+
+        Self {
+            frame: self.frame,
+            baseline: Some(baseline.frame),
+            entities: self.entities.clone(),
+            components,
+        }
+    }
+
+    /// Rebuild the full frame from a delta frame and the baseline it was encoded against.
+    pub fn decode_delta(
+        &self,
+        baseline: &ServerFrame,
+        registry: &NetworkRegistry,
+    ) -> Result<ServerFrame, FrameError> {
+        if let Some(block) = self
+            .components
+            .iter()
+            .find(|b| registry.get(b.id).is_none())
+        {
+            return Err(FrameError::UnknownComponent(block.id));
+        }
+
+        let mut components = vec![];
+        for component in registry.components.iter() {
+            let data = (component.decode_delta)(
+                self.block(component.id),
+                &self.entities,
+                baseline.block(component.id),
+                &baseline.entities,
+            )?;
+            components.push(ComponentBlock {
+                id: component.id,
+                data,
+            });
+        }
+
+        Ok(Self {
+            frame: self.frame,
+            baseline: None,
+            entities: self.entities.clone(),
+            components,
+        })
+    }
+
+    pub fn apply_in_world(
+        &self,
+        world: &World,
+        registry: &NetworkRegistry,
+    ) -> Result<(), FrameError> {
+        for block in self.components.iter() {
+            let component = registry
+                .get(block.id)
+                .ok_or(FrameError::UnknownComponent(block.id))?;
+            (component.apply_in_world)(&block.data, &self.entities, world)?;
+        }
         // Remove entities that are not in the network frame
         world
             .run(|mut all_storages: AllStoragesViewMut| {
@@ -75,17 +312,126 @@ impl ServerFrame {
                 }
             })
             .unwrap();
+
+        Ok(())
+    }
+}
+
+/// Last full frames sent or received, used as baselines for the delta frames.
+#[derive(Debug, Default)]
+pub struct FrameHistory {
+    frames: VecDeque<ServerFrame>,
+}
+
+impl FrameHistory {
+    pub fn insert(&mut self, frame: ServerFrame) {
+        debug_assert!(
+            frame.baseline.is_none(),
+            "Only full frames can be used as baseline."
+        );
+        if self.frames.len() >= FRAME_HISTORY_SIZE {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    pub fn get(&self, frame: u64) -> Option<&ServerFrame> {
+        self.frames.iter().find(|f| f.frame == frame)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
 struct NetworkComponent<T: NetworkState> {
-    // TODO: Serialize as bit not u8.
     bitmask: Vec<bool>,
     values: Vec<T::State>,
 }
 
+impl<T: NetworkState> Default for NetworkComponent<T> {
+    fn default() -> Self {
+        Self {
+            bitmask: vec![],
+            values: vec![],
+        }
+    }
+}
+
+/// Components that changed since the baseline, encoded with `NetworkState::delta`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+struct NetworkComponentDelta<T: NetworkState> {
+    changed: Vec<bool>,
+    // Entities that are still in the frame but lost the component.
+    removed: Vec<bool>,
+    values: Vec<T::Delta>,
+}
+
+impl<T: NetworkState> Default for NetworkComponentDelta<T> {
+    fn default() -> Self {
+        Self {
+            changed: vec![],
+            removed: vec![],
+            values: vec![],
+        }
+    }
+}
+
 impl<T: 'static + Sync + Send + Clone + NetworkState> NetworkComponent<T> {
+    fn deserialize_or_default(data: Option<&[u8]>) -> Result<Self, FrameError> {
+        match data {
+            Some(data) => Ok(deserialize(data)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn serialized_from_world(entities_id: &[EntityId], world: &World) -> Vec<u8> {
+        serialize(&Self::from_world(entities_id, world)).expect("Failed to serialize component.")
+    }
+
+    fn apply_serialized_in_world(
+        data: &[u8],
+        entities_id: &[EntityId],
+        world: &World,
+    ) -> Result<(), FrameError> {
+        let component: Self = deserialize(data)?;
+        component.apply_in_world(entities_id, world);
+        Ok(())
+    }
+
+    fn serialized_delta(
+        data: &[u8],
+        entities_id: &[EntityId],
+        baseline: Option<&[u8]>,
+        baseline_entities_id: &[EntityId],
+    ) -> Result<Option<Vec<u8>>, FrameError> {
+        let component: Self = deserialize(data)?;
+        let baseline = Self::deserialize_or_default(baseline)?;
+        let delta = component.delta(entities_id, &baseline, baseline_entities_id);
+        if delta.values.is_empty() && delta.removed.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serialize(&delta)?))
+    }
+
+    fn serialized_decode_delta(
+        data: Option<&[u8]>,
+        entities_id: &[EntityId],
+        baseline: Option<&[u8]>,
+        baseline_entities_id: &[EntityId],
+    ) -> Result<Vec<u8>, FrameError> {
+        let delta = match data {
+            Some(data) => deserialize(data)?,
+            None => NetworkComponentDelta::default(),
+        };
+        let baseline = Self::deserialize_or_default(baseline)?;
+        let component = Self::decode_delta(&delta, entities_id, &baseline, baseline_entities_id)?;
+        Ok(serialize(&component)?)
+    }
+
     fn from_world(entities_id: &[EntityId], world: &World) -> NetworkComponent<T> {
         let mut bitmask: Vec<bool> = vec![false; entities_id.len()];
         let mut values: Vec<Option<T::State>> = vec![None; entities_id.len()];
@@ -108,31 +454,120 @@ impl<T: 'static + Sync + Send + Clone + NetworkState> NetworkComponent<T> {
         NetworkComponent { bitmask, values }
     }
 
-    fn apply_in_world(&self, entities_id: &[EntityId], world: &World) {
-        let entities_state = entities_id
+    fn states<'a>(&'a self, entities_id: &[EntityId]) -> HashMap<EntityId, &'a T::State> {
+        entities_id
             .iter()
             .zip(self.bitmask.iter())
-            .filter_map(|(id, &presence)| if presence { Some(id) } else { None })
-            .zip(self.values.clone().into_iter());
+            .filter_map(|(id, &presence)| if presence { Some(*id) } else { None })
+            .zip(self.values.iter())
+            .collect()
+    }
+
+    fn delta(
+        &self,
+        entities_id: &[EntityId],
+        baseline: &NetworkComponent<T>,
+        baseline_entities_id: &[EntityId],
+    ) -> NetworkComponentDelta<T> {
+        let baseline_states = baseline.states(baseline_entities_id);
+        let states = self.states(entities_id);
+        let mut changed: Vec<bool> = vec![false; entities_id.len()];
+        let mut removed: Vec<bool> = vec![false; entities_id.len()];
+        let mut values: Vec<T::Delta> = vec![];
+
+        for (i, entity_id) in entities_id.iter().enumerate() {
+            let baseline_state = baseline_states.get(entity_id).copied();
+            match states.get(entity_id) {
+                Some(&state) if baseline_state != Some(state) => {
+                    changed[i] = true;
+                    values.push(T::delta(state, baseline_state));
+                }
+                None if baseline_state.is_some() => removed[i] = true,
+                _ => {}
+            }
+        }
+
+        // Masks without any entity set are sent empty
+        if values.is_empty() {
+            changed.clear();
+        }
+        if !removed.contains(&true) {
+            removed.clear();
+        }
+
+        NetworkComponentDelta {
+            changed,
+            removed,
+            values,
+        }
+    }
+
+    fn decode_delta(
+        delta: &NetworkComponentDelta<T>,
+        entities_id: &[EntityId],
+        baseline: &NetworkComponent<T>,
+        baseline_entities_id: &[EntityId],
+    ) -> Result<NetworkComponent<T>, FrameError> {
+        let baseline_states = baseline.states(baseline_entities_id);
+        let mut bitmask: Vec<bool> = vec![false; entities_id.len()];
+        let mut values: Vec<T::State> = vec![];
+        let mut changed_values = delta.values.iter();
+
+        for (i, entity_id) in entities_id.iter().enumerate() {
+            let baseline_state = baseline_states.get(entity_id).copied();
+            let changed = delta.changed.get(i).copied().unwrap_or(false);
+            let removed = delta.removed.get(i).copied().unwrap_or(false);
+            let state = if removed {
+                None
+            } else if changed {
+                let value = changed_values.next().ok_or(FrameError::InvalidDelta)?;
+                let state = T::apply_delta(baseline_state, value.clone())
+                    .ok_or(FrameError::InvalidDelta)?;
+                Some(state)
+            } else {
+                baseline_state.cloned()
+            };
+
+            if let Some(state) = state {
+                bitmask[i] = true;
+                values.push(state);
+            }
+        }
+
+        Ok(NetworkComponent { bitmask, values })
+    }
+
+    fn apply_in_world(&self, entities_id: &[EntityId], world: &World) {
+        let mut states = self.values.iter().cloned();
 
-        // TODO: instead of filter map we could remove component when is None
         world
             .run(
                 |mut entities: EntitiesViewMut,
                  mut components: ViewMut<T>,
                  mut mapping: UniqueViewMut<EntityMapping>| {
-                    for (entity_id, state) in entities_state {
-                        if let Some(mapped_id) = mapping.get(entity_id) {
-                            if let Ok(mut component) = (&mut components).get(*mapped_id) {
-                                component.update_from_state(state);
-                            } else {
+                    for (entity_id, &presence) in entities_id.iter().zip(self.bitmask.iter()) {
+                        let mapped_id = mapping.get(entity_id).copied();
+                        let state = if presence { states.next() } else { None };
+                        match (mapped_id, state) {
+                            (Some(mapped_id), Some(state)) => {
+                                if let Ok(mut component) = (&mut components).get(mapped_id) {
+                                    component.update_from_state(state);
+                                } else {
+                                    let component = T::from_state(state);
+                                    entities.add_component(mapped_id, &mut components, component);
+                                }
+                            }
+                            (None, Some(state)) => {
                                 let component = T::from_state(state);
-                                entities.add_component(*mapped_id, &mut components, component);
+                                let client_entity_id =
+                                    entities.add_entity(&mut components, component);
+                                mapping.insert(*entity_id, client_entity_id);
+                            }
+                            // The entity still exists on the server without this component
+                            (Some(mapped_id), None) => {
+                                Remove::<(T,)>::remove((&mut components,), mapped_id);
                             }
-                        } else {
-                            let component = T::from_state(state);
-                            let client_entity_id = entities.add_entity(&mut components, component);
-                            mapping.insert(*entity_id, client_entity_id);
+                            (None, None) => {}
                         }
                     }
                 },
@@ -1,6 +1,10 @@
-use macroquad::prelude::*;
+use glam::{vec2, Vec2};
+#[cfg(feature = "render")]
+use macroquad::prelude::{draw_rectangle_lines, Color, BLUE, RED};
 use shipyard::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::math::Rect;
 
 #[derive(Debug)]
 pub struct StaticTiledLayer {
@@ -9,7 +13,8 @@ pub struct StaticTiledLayer {
     tile_height: f32,
     width: usize,
     tag: u8,
-    debug_color: Color,
+    // RGBA color used by the debug rendering.
+    debug_color: [f32; 4],
 }
 
 #[derive(Debug)]
@@ -17,6 +22,8 @@ pub struct Physics {
     static_tiled_layers: Vec<StaticTiledLayer>,
     solids: HashMap<EntityId, Collider>,
     actors: HashMap<EntityId, Collider>,
+    // Actor colliders of the last ticks, used to rewind targets for lag compensation.
+    actors_history: VecDeque<(u64, HashMap<EntityId, Rect>)>,
 }
 
 #[derive(Clone, Debug)]
@@ -48,6 +55,7 @@ impl Physics {
             static_tiled_layers: vec![],
             actors: HashMap::new(),
             solids: HashMap::new(),
+            actors_history: VecDeque::new(),
         }
     }
 
@@ -58,7 +66,7 @@ impl Physics {
         tile_height: f32,
         width: usize,
         tag: u8,
-        debug_color: Color,
+        debug_color: [f32; 4],
     ) {
         self.static_tiled_layers.push(StaticTiledLayer {
             static_colliders,
@@ -348,6 +356,10 @@ impl Physics {
         self.actors[&actor].squished
     }
 
+    pub fn has_actor(&self, actor: EntityId) -> bool {
+        self.actors.contains_key(&actor)
+    }
+
     pub fn actor_pos(&self, actor: EntityId) -> Vec2 {
         self.actors[&actor].pos
     }
@@ -367,8 +379,36 @@ impl Physics {
             .rect()
             .overlaps(&self.actors[&target].rect())
     }
+
+    pub fn record_actors_history(&mut self, tick: u64, max_ticks: usize) {
+        let actors = self
+            .actors
+            .iter()
+            .map(|(entity_id, collider)| (*entity_id, collider.rect()))
+            .collect();
+        self.actors_history.push_back((tick, actors));
+
+        while self.actors_history.len() > max_ticks {
+            self.actors_history.pop_front();
+        }
+    }
+
+    /// Checks the collider against where the target was at the given tick,
+    /// uses the current target position when the tick is not in the history.
+    pub fn overlaps_actor_at(&self, collider: EntityId, target: EntityId, tick: u64) -> bool {
+        let target_rect = self
+            .actors_history
+            .iter()
+            .rev()
+            .find(|(history_tick, _)| *history_tick == tick)
+            .and_then(|(_, actors)| actors.get(&target).copied())
+            .unwrap_or_else(|| self.actors[&target].rect());
+
+        self.actors[&collider].rect().overlaps(&target_rect)
+    }
 }
 
+#[cfg(feature = "render")]
 pub fn render_physics(upscale: f32, world: UniqueView<Physics>) {
     // Draw Static Layer
     for layer in world.static_tiled_layers.iter() {
@@ -382,7 +422,12 @@ pub fn render_physics(upscale: f32, world: UniqueView<Physics>) {
                     layer.tile_width * upscale,
                     layer.tile_height * upscale,
                     1.0 * upscale,
-                    layer.debug_color,
+                    Color::new(
+                        layer.debug_color[0],
+                        layer.debug_color[1],
+                        layer.debug_color[2],
+                        layer.debug_color[3],
+                    ),
                 )
             }
         }
@@ -397,6 +442,7 @@ pub fn render_physics(upscale: f32, world: UniqueView<Physics>) {
     }
 }
 
+#[cfg(feature = "render")]
 pub fn draw_collider(collider: &Collider, color: Color) {
     draw_rectangle_lines(
         collider.pos.x,
@@ -1,27 +1,39 @@
-use std::net::SocketAddr;
-
-use glam::Vec2;
+use glam::{vec2, Vec2};
 use serde::{Deserialize, Serialize};
+use shipyard::EntityId;
 
 use derive::NetworkState;
 
+use crate::message::MessageError;
+use crate::physics::Physics;
 use crate::timer::TimerSimple;
+use crate::{PlayerId, Team};
 
-#[derive(Debug, Clone, Serialize, Deserialize, NetworkState)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, NetworkState)]
 pub struct Player {
-    pub client_id: SocketAddr,
+    pub player_id: PlayerId,
+    #[network(quantize = 0.001)]
     pub direction: Vec2,
+    #[network(skip)]
     pub fireball_cooldown: TimerSimple,
+    #[network(quantize = 0.01)]
     pub fireball_charge: f32,
+    #[network(skip)]
     pub fireball_max_charge: f32,
     pub dash_cooldown: TimerSimple,
     pub dash_duration: f32,
     pub current_dash_duration: f32,
+    // Replicated for the client prediction reconciliation.
+    #[network(quantize = 0.01)]
     pub speed: Vec2,
+    // Last input sequence simulated by the server, used for client reconciliation.
+    pub input_sequence: u32,
+    // Set in the team modes, teammates are tinted with the same color.
+    pub team: Option<Team>,
 }
 
 impl Player {
-    pub fn new(client_id: SocketAddr) -> Self {
+    pub fn new(player_id: PlayerId) -> Self {
         let mut fireball_cooldown = TimerSimple::new(1.5);
         fireball_cooldown.finish();
 
@@ -29,7 +41,7 @@ impl Player {
         dash_cooldown.finish();
 
         Self {
-            client_id,
+            player_id,
             direction: Vec2::ZERO,
             fireball_cooldown,
             dash_cooldown,
@@ -38,6 +50,8 @@ impl Player {
             fireball_charge: 0.0,
             current_dash_duration: 0.0,
             speed: Vec2::ZERO,
+            input_sequence: 0,
+            team: None,
         }
     }
 }
@@ -52,6 +66,9 @@ pub struct PlayerInput {
     pub dash: bool,
     pub fire: bool,
     pub direction: Vec2,
+    pub sequence: u32,
+    // Server tick the client was rendering when this input was sampled.
+    pub view_tick: u64,
 }
 
 impl Default for PlayerInput {
@@ -65,10 +82,118 @@ impl Default for PlayerInput {
             fire: false,
             jump: false,
             direction: Vec2::ZERO,
+            sequence: 0,
+            view_tick: 0,
+        }
+    }
+}
+
+impl PlayerInput {
+    // The direction is zero or normalized, anything else was not sent by a well behaved client.
+    pub fn validate(&self) -> Result<(), MessageError> {
+        let direction = self.direction;
+        let length = direction.length();
+        if !direction.is_finite() || (length != 0. && (length - 1.).abs() > 0.01) {
+            return Err(MessageError::InvalidDirection);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameplayConfig {
+    pub dash_speed: f32,
+    pub jump_speed: f32,
+    pub walk_speed: f32,
+    pub player_gravity: f32,
+    pub dash_duration: f32,
+    pub dash_cooldown: f32,
+    pub fireball_cooldown: f32,
+    // Maximum ticks a target is rewound when checking projectile hits.
+    pub max_rewind_ticks: u64,
+}
+
+impl Default for GameplayConfig {
+    fn default() -> Self {
+        Self {
+            dash_speed: 160.,
+            jump_speed: 180.,
+            walk_speed: 80.,
+            player_gravity: 550.,
+            dash_duration: 0.,
+            dash_cooldown: 0.,
+            fireball_cooldown: 0.,
+            max_rewind_ticks: 12,
         }
     }
 }
 
+/// Simulates one step of the player movement, the server runs it for every player
+/// and the client runs it to predict its own player. Returns if the player is on the ground.
+pub fn update_player_movement(
+    entity_id: EntityId,
+    player: &mut Player,
+    input: &PlayerInput,
+    physics: &mut Physics,
+    gameplay: &GameplayConfig,
+    frame_time: f32,
+) -> bool {
+    player.dash_cooldown.update(frame_time);
+
+    let x = (input.right as i8 - input.left as i8) as f32;
+    let y = (input.down as i8 - input.up as i8) as f32;
+    let movement_direction = vec2(x, y);
+    player.direction = if input.direction.length() != 0.0 {
+        input.direction.normalize()
+    } else {
+        input.direction
+    };
+
+    if input.dash && player.dash_cooldown.is_finished() {
+        player.dash_cooldown.reset();
+        player.current_dash_duration = player.dash_duration;
+
+        // If there is no player input use player facing direction
+        let dash_direction = if movement_direction.length() != 0.0 {
+            movement_direction.normalize()
+        } else {
+            vec2(input.direction.x.signum(), 0.)
+        };
+        player.speed = dash_direction * gameplay.dash_speed;
+    }
+
+    let pos = physics.actor_pos(entity_id);
+    let on_ground = physics.collide_check(entity_id, pos + vec2(0., 1.));
+
+    if player.current_dash_duration > 0.0 {
+        player.current_dash_duration -= frame_time;
+        if player.current_dash_duration <= 0.0 {
+            player.speed = player.speed.normalize() * gameplay.walk_speed;
+        }
+    } else {
+        if !on_ground {
+            player.speed.y += gameplay.player_gravity * frame_time;
+        } else {
+            player.speed.y = gameplay.player_gravity * frame_time;
+        }
+
+        player.speed.x = movement_direction.x * gameplay.walk_speed;
+        if input.jump && on_ground {
+            player.speed.y = -gameplay.jump_speed;
+        }
+    }
+
+    if physics.move_h(entity_id, player.speed.x * frame_time) {
+        player.current_dash_duration = 0.;
+    }
+    if physics.move_v(entity_id, player.speed.y * frame_time) {
+        player.current_dash_duration = 0.;
+        player.speed.y = 0.0;
+    }
+
+    on_ground
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CastTarget {
     pub position: Vec2,
@@ -7,17 +7,22 @@ use glam::Vec2;
 
 use derive::NetworkState;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ProjectileType {
     Fireball,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, NetworkState)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, NetworkState)]
 pub struct Projectile {
     pub projectile_type: ProjectileType,
     pub owner: EntityId,
+    #[network(skip)]
     pub duration: Duration,
+    #[network(skip)]
     pub speed: Vec2,
+    // Ticks the targets are rewound to match what the owner saw when casting.
+    #[network(skip)]
+    pub rewind_ticks: u64,
 }
 
 impl Projectile {
@@ -27,6 +32,7 @@ impl Projectile {
             speed,
             projectile_type,
             duration: Duration::from_secs(2),
+            rewind_ticks: 0,
         }
     }
 }
@@ -33,7 +33,7 @@ impl Timer {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct TimerSimple {
     duration: f32,
     current_duration: f32,
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub use self::conditioner::{
+    ConditionedTransport, ConditionerHandle, ConditionsPreset, NetworkConditions,
+};
+pub use self::memory::{
+    memory_transport, MemoryClientTransport, MemoryConnector, MemoryServerTransport,
+};
+pub use self::udp::{UdpClientTransport, UdpServerTransport};
+
+mod conditioner;
+mod memory;
+mod udp;
+
+#[derive(Debug)]
+pub struct TransportError(pub String);
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    ClientConnected(SocketAddr),
+    // The reason is only used for logging.
+    ClientDisconnected(SocketAddr, String),
+}
+
+/// Connections from the server to the clients, messages are sent and received by channel.
+pub trait ServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError>;
+    fn get_event(&mut self) -> Option<ServerEvent>;
+    fn clients_id(&self) -> Vec<SocketAddr>;
+    fn receive_message(&mut self, client_id: &SocketAddr, channel_id: u8) -> Option<Vec<u8>>;
+    fn send_message(
+        &mut self,
+        client_id: &SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    ) -> Result<(), TransportError>;
+    fn broadcast_message(&mut self, channel_id: u8, message: Vec<u8>);
+    fn send_packets(&mut self) -> Result<(), TransportError>;
+    // Closes the connection of the client, it is reported as a disconnect event.
+    fn disconnect(&mut self, client_id: &SocketAddr);
+}
+
+/// Connection from a client to the server.
+pub trait ClientTransport {
+    // Identifies the client in the server, also used as the player client id.
+    fn id(&self) -> SocketAddr;
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError>;
+    fn receive_message(&mut self, channel_id: u8) -> Option<Vec<u8>>;
+    fn send_message(&mut self, channel_id: u8, message: Vec<u8>) -> Result<(), TransportError>;
+    fn send_packets(&mut self) -> Result<(), TransportError>;
+}
+
+/// Serves the clients of several transports as one, used by the hosting client
+/// to accept remote clients over UDP and its own client in memory.
+pub struct MultiServerTransport {
+    transports: Vec<Box<dyn ServerTransport>>,
+    // Index of the transport of each client.
+    clients: HashMap<SocketAddr, usize>,
+    events: VecDeque<ServerEvent>,
+}
+
+impl MultiServerTransport {
+    pub fn new(transports: Vec<Box<dyn ServerTransport>>) -> Self {
+        Self {
+            transports,
+            clients: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    fn transport(&mut self, client_id: &SocketAddr) -> Option<&mut Box<dyn ServerTransport>> {
+        let index = *self.clients.get(client_id)?;
+        self.transports.get_mut(index)
+    }
+}
+
+impl ServerTransport for MultiServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError> {
+        let mut result = Ok(());
+        for (index, transport) in self.transports.iter_mut().enumerate() {
+            if let Err(e) = transport.update(duration) {
+                result = Err(e);
+            }
+
+            while let Some(event) = transport.get_event() {
+                match &event {
+                    ServerEvent::ClientConnected(client_id) => {
+                        self.clients.insert(*client_id, index);
+                    }
+                    ServerEvent::ClientDisconnected(client_id, _) => {
+                        self.clients.remove(client_id);
+                    }
+                }
+                self.events.push_back(event);
+            }
+        }
+        result
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.events.pop_front()
+    }
+
+    fn clients_id(&self) -> Vec<SocketAddr> {
+        self.transports
+            .iter()
+            .flat_map(|transport| transport.clients_id())
+            .collect()
+    }
+
+    fn receive_message(&mut self, client_id: &SocketAddr, channel_id: u8) -> Option<Vec<u8>> {
+        self.transport(client_id)?
+            .receive_message(client_id, channel_id)
+    }
+
+    fn send_message(
+        &mut self,
+        client_id: &SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    ) -> Result<(), TransportError> {
+        match self.transport(client_id) {
+            Some(transport) => transport.send_message(client_id, channel_id, message),
+            None => Err(TransportError(format!("client {} not found", client_id))),
+        }
+    }
+
+    fn broadcast_message(&mut self, channel_id: u8, message: Vec<u8>) {
+        for transport in self.transports.iter_mut() {
+            transport.broadcast_message(channel_id, message.clone());
+        }
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        let mut result = Ok(());
+        for transport in self.transports.iter_mut() {
+            if let Err(e) = transport.send_packets() {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn disconnect(&mut self, client_id: &SocketAddr) {
+        if let Some(transport) = self.transport(client_id) {
+            transport.disconnect(client_id);
+        }
+    }
+}
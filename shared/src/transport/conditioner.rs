@@ -0,0 +1,402 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{ClientTransport, ServerEvent, ServerTransport, TransportError};
+use crate::Channel;
+
+/// Simulated conditions of the connection, applied to the messages in both directions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkConditions {
+    // One-way delay added to each message, in milliseconds.
+    pub latency: f32,
+    // Random delay added on top of the latency, up to this value in milliseconds.
+    pub jitter: f32,
+    // Chances from 0 to 1 of each message to be lost, duplicated or delivered out of order.
+    pub loss: f32,
+    pub duplication: f32,
+    pub reordering: f32,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        ConditionsPreset::Off.conditions()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConditionsPreset {
+    Off,
+    Lan,
+    WiFi,
+    Mobile,
+}
+
+impl ConditionsPreset {
+    pub const ALL: [ConditionsPreset; 4] = [
+        ConditionsPreset::Off,
+        ConditionsPreset::Lan,
+        ConditionsPreset::WiFi,
+        ConditionsPreset::Mobile,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConditionsPreset::Off => "Off",
+            ConditionsPreset::Lan => "LAN",
+            ConditionsPreset::WiFi => "Wi-Fi",
+            ConditionsPreset::Mobile => "Mobile",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase().replace('-', "");
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name().to_lowercase().replace('-', "") == name)
+    }
+
+    pub fn conditions(&self) -> NetworkConditions {
+        let (latency, jitter, loss, duplication, reordering) = match self {
+            ConditionsPreset::Off => (0., 0., 0., 0., 0.),
+            ConditionsPreset::Lan => (1., 1., 0., 0., 0.),
+            ConditionsPreset::WiFi => (15., 10., 0.01, 0.001, 0.01),
+            ConditionsPreset::Mobile => (60., 30., 0.03, 0.005, 0.03),
+        };
+        NetworkConditions {
+            latency,
+            jitter,
+            loss,
+            duplication,
+            reordering,
+        }
+    }
+}
+
+/// Shared access to the conditions of a conditioned transport, used to change them while running.
+#[derive(Debug, Clone, Default)]
+pub struct ConditionerHandle(Arc<Mutex<NetworkConditions>>);
+
+impl ConditionerHandle {
+    pub fn new(conditions: NetworkConditions) -> Self {
+        Self(Arc::new(Mutex::new(conditions)))
+    }
+
+    pub fn get(&self) -> NetworkConditions {
+        *self.0.lock().unwrap()
+    }
+
+    pub fn set(&self, conditions: NetworkConditions) {
+        *self.0.lock().unwrap() = conditions;
+    }
+}
+
+// Xorshift generator, the simulation does not need a good source of randomness.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state can not be zero
+        Self(seed | 1)
+    }
+
+    fn from_time() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        Self::new(seed)
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
+
+struct DelayedMessage {
+    deliver_at: Duration,
+    peer: SocketAddr,
+    channel_id: u8,
+    message: Vec<u8>,
+}
+
+// Messages held back until their delivery time.
+struct DelayQueue {
+    messages: Vec<DelayedMessage>,
+    // Last delivery time of each reliable channel, reliable messages are never reordered.
+    last_reliable: HashMap<(SocketAddr, u8), Duration>,
+}
+
+impl DelayQueue {
+    fn new() -> Self {
+        Self {
+            messages: vec![],
+            last_reliable: HashMap::new(),
+        }
+    }
+
+    fn push(
+        &mut self,
+        conditions: &NetworkConditions,
+        rng: &mut Rng,
+        now: Duration,
+        peer: SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    ) {
+        let delay = |rng: &mut Rng| {
+            let millis = conditions.latency + conditions.jitter * rng.next_f32();
+            Duration::from_secs_f32(millis.max(0.) / 1000.)
+        };
+        let mut deliver_at = now + delay(rng);
+
+        if channel_id != Channel::Unreliable.id() {
+            // Reliable messages are not lost, they arrive after being resent
+            if rng.chance(conditions.loss) {
+                deliver_at += delay(rng) * 2;
+            }
+            let last = self.last_reliable.entry((peer, channel_id)).or_default();
+            deliver_at = deliver_at.max(*last);
+            *last = deliver_at;
+        } else {
+            if rng.chance(conditions.loss) {
+                return;
+            }
+            if rng.chance(conditions.reordering) {
+                deliver_at += delay(rng);
+            }
+            if rng.chance(conditions.duplication) {
+                self.messages.push(DelayedMessage {
+                    deliver_at: now + delay(rng),
+                    peer,
+                    channel_id,
+                    message: message.clone(),
+                });
+            }
+        }
+
+        self.messages.push(DelayedMessage {
+            deliver_at,
+            peer,
+            channel_id,
+            message,
+        });
+    }
+
+    // Removes the messages that should be delivered by now, in delivery order.
+    fn pop_ready(&mut self, now: Duration) -> Vec<DelayedMessage> {
+        let (mut ready, pending): (Vec<_>, Vec<_>) = self
+            .messages
+            .drain(..)
+            .partition(|message| message.deliver_at <= now);
+        self.messages = pending;
+        ready.sort_by_key(|message| message.deliver_at);
+        ready
+    }
+
+    fn remove_peer(&mut self, peer: &SocketAddr) {
+        self.messages.retain(|message| message.peer != *peer);
+        self.last_reliable.retain(|(p, _), _| p != peer);
+    }
+}
+
+const CHANNELS: [Channel; 3] = [
+    Channel::Reliable,
+    Channel::ReliableCritical,
+    Channel::Unreliable,
+];
+
+/// Wraps a transport to simulate bad connections, messages sent and received
+/// are delayed, lost, duplicated or reordered according to the conditions.
+pub struct ConditionedTransport<T> {
+    inner: T,
+    conditions: ConditionerHandle,
+    rng: Rng,
+    // Time since the transport was created, advanced in each update.
+    now: Duration,
+    incoming: DelayQueue,
+    outgoing: DelayQueue,
+    received: HashMap<(SocketAddr, u8), VecDeque<Vec<u8>>>,
+}
+
+impl<T> ConditionedTransport<T> {
+    pub fn new(inner: T, conditions: ConditionerHandle) -> Self {
+        Self::with_rng(inner, conditions, Rng::from_time())
+    }
+
+    /// Same random losses and delays for the same seed, used to reproduce a run.
+    pub fn with_seed(inner: T, conditions: ConditionerHandle, seed: u64) -> Self {
+        Self::with_rng(inner, conditions, Rng::new(seed))
+    }
+
+    fn with_rng(inner: T, conditions: ConditionerHandle, rng: Rng) -> Self {
+        Self {
+            inner,
+            conditions,
+            rng,
+            now: Duration::ZERO,
+            incoming: DelayQueue::new(),
+            outgoing: DelayQueue::new(),
+            received: HashMap::new(),
+        }
+    }
+
+    fn delay_incoming(&mut self, peer: SocketAddr, channel_id: u8, message: Vec<u8>) {
+        let conditions = self.conditions.get();
+        self.incoming.push(
+            &conditions,
+            &mut self.rng,
+            self.now,
+            peer,
+            channel_id,
+            message,
+        );
+    }
+
+    fn delay_outgoing(&mut self, peer: SocketAddr, channel_id: u8, message: Vec<u8>) {
+        let conditions = self.conditions.get();
+        self.outgoing.push(
+            &conditions,
+            &mut self.rng,
+            self.now,
+            peer,
+            channel_id,
+            message,
+        );
+    }
+
+    fn deliver_incoming(&mut self) {
+        for message in self.incoming.pop_ready(self.now) {
+            self.received
+                .entry((message.peer, message.channel_id))
+                .or_default()
+                .push_back(message.message);
+        }
+    }
+
+    fn receive(&mut self, peer: SocketAddr, channel_id: u8) -> Option<Vec<u8>> {
+        self.received.get_mut(&(peer, channel_id))?.pop_front()
+    }
+}
+
+impl<T: ServerTransport> ServerTransport for ConditionedTransport<T> {
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError> {
+        self.now += duration;
+        let result = self.inner.update(duration);
+
+        for client_id in self.inner.clients_id() {
+            for channel in CHANNELS.iter() {
+                while let Some(message) = self.inner.receive_message(&client_id, channel.id()) {
+                    self.delay_incoming(client_id, channel.id(), message);
+                }
+            }
+        }
+        self.deliver_incoming();
+
+        result
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        let event = self.inner.get_event()?;
+        if let ServerEvent::ClientDisconnected(client_id, _) = &event {
+            self.incoming.remove_peer(client_id);
+            self.outgoing.remove_peer(client_id);
+            self.received.retain(|(peer, _), _| peer != client_id);
+        }
+        Some(event)
+    }
+
+    fn clients_id(&self) -> Vec<SocketAddr> {
+        self.inner.clients_id()
+    }
+
+    fn receive_message(&mut self, client_id: &SocketAddr, channel_id: u8) -> Option<Vec<u8>> {
+        self.receive(*client_id, channel_id)
+    }
+
+    fn send_message(
+        &mut self,
+        client_id: &SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    ) -> Result<(), TransportError> {
+        self.delay_outgoing(*client_id, channel_id, message);
+        Ok(())
+    }
+
+    fn broadcast_message(&mut self, channel_id: u8, message: Vec<u8>) {
+        // Each client has its own connection conditions
+        for client_id in self.inner.clients_id() {
+            self.delay_outgoing(client_id, channel_id, message.clone());
+        }
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        let mut result = Ok(());
+        for message in self.outgoing.pop_ready(self.now) {
+            // The client may have disconnected while the message was delayed
+            if let Err(e) =
+                self.inner
+                    .send_message(&message.peer, message.channel_id, message.message)
+            {
+                result = result.and(Err(e));
+            }
+        }
+        result.and(self.inner.send_packets())
+    }
+
+    fn disconnect(&mut self, client_id: &SocketAddr) {
+        // Pending messages are dropped with the disconnect event
+        self.inner.disconnect(client_id);
+    }
+}
+
+impl<T: ClientTransport> ClientTransport for ConditionedTransport<T> {
+    fn id(&self) -> SocketAddr {
+        self.inner.id()
+    }
+
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError> {
+        self.now += duration;
+        self.inner.update(duration)?;
+
+        let id = self.inner.id();
+        for channel in CHANNELS.iter() {
+            while let Some(message) = self.inner.receive_message(channel.id()) {
+                self.delay_incoming(id, channel.id(), message);
+            }
+        }
+        self.deliver_incoming();
+
+        Ok(())
+    }
+
+    fn receive_message(&mut self, channel_id: u8) -> Option<Vec<u8>> {
+        self.receive(self.inner.id(), channel_id)
+    }
+
+    fn send_message(&mut self, channel_id: u8, message: Vec<u8>) -> Result<(), TransportError> {
+        self.delay_outgoing(self.inner.id(), channel_id, message);
+        Ok(())
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        // The first error is returned after the other messages are sent
+        let mut result = Ok(());
+        for message in self.outgoing.pop_ready(self.now) {
+            if let Err(e) = self.inner.send_message(message.channel_id, message.message) {
+                result = result.and(Err(e));
+            }
+        }
+        result.and(self.inner.send_packets())
+    }
+}
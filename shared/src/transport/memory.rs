@@ -0,0 +1,208 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{ClientTransport, ServerEvent, ServerTransport, TransportError};
+
+type Message = (u8, Vec<u8>);
+
+// One side of an in-memory connection, messages are delivered in order without loss.
+struct MemoryConnection {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    received: HashMap<u8, VecDeque<Vec<u8>>>,
+}
+
+impl MemoryConnection {
+    fn pair() -> (Self, Self) {
+        let (client_sender, server_receiver) = channel();
+        let (server_sender, client_receiver) = channel();
+        let client = Self {
+            sender: client_sender,
+            receiver: client_receiver,
+            received: HashMap::new(),
+        };
+        let server = Self {
+            sender: server_sender,
+            receiver: server_receiver,
+            received: HashMap::new(),
+        };
+        (client, server)
+    }
+
+    // Returns false when the other side was dropped.
+    fn receive_all(&mut self) -> bool {
+        loop {
+            match self.receiver.try_recv() {
+                Ok((channel_id, message)) => {
+                    self.received
+                        .entry(channel_id)
+                        .or_default()
+                        .push_back(message);
+                }
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    fn receive_message(&mut self, channel_id: u8) -> Option<Vec<u8>> {
+        self.received.get_mut(&channel_id)?.pop_front()
+    }
+
+    fn send_message(&self, channel_id: u8, message: Vec<u8>) -> Result<(), TransportError> {
+        self.sender
+            .send((channel_id, message))
+            .map_err(|_| TransportError("memory connection closed".to_string()))
+    }
+}
+
+/// Creates a server transport that lives in the same process as its clients,
+/// the connector is used to create the clients.
+pub fn memory_transport() -> (MemoryServerTransport, MemoryConnector) {
+    let (sender, receiver) = channel();
+    let server = MemoryServerTransport {
+        new_connections: receiver,
+        clients: HashMap::new(),
+        events: VecDeque::new(),
+    };
+    let connector = MemoryConnector {
+        sender,
+        next_port: Arc::new(AtomicU16::new(1)),
+    };
+    (server, connector)
+}
+
+#[derive(Clone)]
+pub struct MemoryConnector {
+    sender: Sender<(SocketAddr, MemoryConnection)>,
+    next_port: Arc<AtomicU16>,
+}
+
+impl MemoryConnector {
+    /// Creates a client, it is accepted in the next server update.
+    pub fn connect(&self) -> MemoryClientTransport {
+        // Unspecified addresses are never the source of a remote client
+        let port = self.next_port.fetch_add(1, Ordering::Relaxed);
+        let id = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+        let (client, server) = MemoryConnection::pair();
+        // When the server was dropped the client fails in its first update
+        let _ = self.sender.send((id, server));
+
+        MemoryClientTransport {
+            id,
+            connection: client,
+        }
+    }
+}
+
+pub struct MemoryServerTransport {
+    new_connections: Receiver<(SocketAddr, MemoryConnection)>,
+    clients: HashMap<SocketAddr, MemoryConnection>,
+    events: VecDeque<ServerEvent>,
+}
+
+impl ServerTransport for MemoryServerTransport {
+    fn update(&mut self, _duration: Duration) -> Result<(), TransportError> {
+        while let Ok((client_id, connection)) = self.new_connections.try_recv() {
+            self.clients.insert(client_id, connection);
+            self.events
+                .push_back(ServerEvent::ClientConnected(client_id));
+        }
+
+        let mut disconnected = vec![];
+        for (client_id, connection) in self.clients.iter_mut() {
+            if !connection.receive_all() {
+                disconnected.push(*client_id);
+            }
+        }
+        for client_id in disconnected {
+            self.clients.remove(&client_id);
+            self.events.push_back(ServerEvent::ClientDisconnected(
+                client_id,
+                "client dropped".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        self.events.pop_front()
+    }
+
+    fn clients_id(&self) -> Vec<SocketAddr> {
+        self.clients.keys().copied().collect()
+    }
+
+    fn receive_message(&mut self, client_id: &SocketAddr, channel_id: u8) -> Option<Vec<u8>> {
+        self.clients.get_mut(client_id)?.receive_message(channel_id)
+    }
+
+    fn send_message(
+        &mut self,
+        client_id: &SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    ) -> Result<(), TransportError> {
+        match self.clients.get(client_id) {
+            Some(connection) => connection.send_message(channel_id, message),
+            None => Err(TransportError(format!("client {} not found", client_id))),
+        }
+    }
+
+    fn broadcast_message(&mut self, channel_id: u8, message: Vec<u8>) {
+        for connection in self.clients.values() {
+            // Dropped clients are removed in the next update
+            let _ = connection.send_message(channel_id, message.clone());
+        }
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    fn disconnect(&mut self, client_id: &SocketAddr) {
+        // The client notices it in its next update
+        if self.clients.remove(client_id).is_some() {
+            self.events.push_back(ServerEvent::ClientDisconnected(
+                *client_id,
+                "disconnected by the server".to_string(),
+            ));
+        }
+    }
+}
+
+pub struct MemoryClientTransport {
+    id: SocketAddr,
+    connection: MemoryConnection,
+}
+
+impl ClientTransport for MemoryClientTransport {
+    fn id(&self) -> SocketAddr {
+        self.id
+    }
+
+    fn update(&mut self, _duration: Duration) -> Result<(), TransportError> {
+        if self.connection.receive_all() {
+            Ok(())
+        } else {
+            Err(TransportError("disconnected from the server".to_string()))
+        }
+    }
+
+    fn receive_message(&mut self, channel_id: u8) -> Option<Vec<u8>> {
+        self.connection.receive_message(channel_id)
+    }
+
+    fn send_message(&mut self, channel_id: u8, message: Vec<u8>) -> Result<(), TransportError> {
+        self.connection.send_message(channel_id, message)
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
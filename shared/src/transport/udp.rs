@@ -0,0 +1,123 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use renet_udp::{
+    client::UdpClient,
+    renet::remote_connection::ConnectionConfig,
+    server::{ServerEvent as UdpServerEvent, UdpServer},
+};
+
+use super::{ClientTransport, ServerEvent, ServerTransport, TransportError};
+use crate::channels_config;
+
+fn connection_config() -> ConnectionConfig {
+    ConnectionConfig {
+        channels_config: channels_config(),
+        ..Default::default()
+    }
+}
+
+pub struct UdpServerTransport {
+    server: UdpServer,
+}
+
+impl UdpServerTransport {
+    pub fn new(addr: SocketAddr, max_clients: usize) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind(addr)?;
+        let server = UdpServer::new(max_clients, connection_config(), socket)?;
+        Ok(Self { server })
+    }
+}
+
+impl ServerTransport for UdpServerTransport {
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError> {
+        self.server
+            .update(duration)
+            .map_err(|e| TransportError(e.to_string()))
+    }
+
+    fn get_event(&mut self) -> Option<ServerEvent> {
+        match self.server.get_event()? {
+            UdpServerEvent::ClientConnected(id) => Some(ServerEvent::ClientConnected(id)),
+            UdpServerEvent::ClientDisconnected(id, reason) => {
+                Some(ServerEvent::ClientDisconnected(id, format!("{:?}", reason)))
+            }
+        }
+    }
+
+    fn clients_id(&self) -> Vec<SocketAddr> {
+        self.server.clients_id()
+    }
+
+    fn receive_message(&mut self, client_id: &SocketAddr, channel_id: u8) -> Option<Vec<u8>> {
+        self.server.receive_message(client_id, channel_id)
+    }
+
+    fn send_message(
+        &mut self,
+        client_id: &SocketAddr,
+        channel_id: u8,
+        message: Vec<u8>,
+    ) -> Result<(), TransportError> {
+        self.server
+            .send_message(client_id, channel_id, message)
+            .map_err(|e| TransportError(e.to_string()))
+    }
+
+    fn broadcast_message(&mut self, channel_id: u8, message: Vec<u8>) {
+        self.server.broadcast_message(channel_id, message);
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        self.server
+            .send_packets()
+            .map_err(|e| TransportError(e.to_string()))
+    }
+
+    fn disconnect(&mut self, client_id: &SocketAddr) {
+        self.server.disconnect(client_id);
+    }
+}
+
+pub struct UdpClientTransport {
+    id: SocketAddr,
+    client: UdpClient,
+}
+
+impl UdpClientTransport {
+    pub fn new(addr: SocketAddr, server_addr: SocketAddr) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind(addr)?;
+        let id = socket.local_addr()?;
+        let client = UdpClient::new(socket, server_addr, connection_config())?;
+        Ok(Self { id, client })
+    }
+}
+
+impl ClientTransport for UdpClientTransport {
+    fn id(&self) -> SocketAddr {
+        self.id
+    }
+
+    fn update(&mut self, duration: Duration) -> Result<(), TransportError> {
+        self.client
+            .update(duration)
+            .map_err(|e| TransportError(e.to_string()))
+    }
+
+    fn receive_message(&mut self, channel_id: u8) -> Option<Vec<u8>> {
+        self.client.receive_message(channel_id)
+    }
+
+    fn send_message(&mut self, channel_id: u8, message: Vec<u8>) -> Result<(), TransportError> {
+        self.client
+            .send_message(channel_id, message)
+            .map_err(|e| TransportError(e.to_string()))
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        self.client
+            .send_packets()
+            .map_err(|e| TransportError(e.to_string()))
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::f32::consts::{PI, TAU};
+use std::fmt::Debug;
+
+use glam::vec2;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use shared::network::bits::{
+    angle, deserialize, position, serialize, BitReader, BitWriter, Error, POSITION_BOUNDS,
+};
+
+fn roundtrip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) -> Vec<u8> {
+    let data = serialize(&value).unwrap();
+    let decoded: T = deserialize(&data).unwrap();
+    assert_eq!(decoded, value);
+    data
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Message {
+    Unit,
+    Newtype(u32),
+    Tuple(i8, bool),
+    Struct { name: String, position: (f32, f32) },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Entity {
+    id: u64,
+    alive: bool,
+    owner: Option<u16>,
+    tags: Vec<String>,
+}
+
+#[test]
+fn primitives_roundtrip() {
+    for &value in [true, false].iter() {
+        roundtrip(value);
+    }
+    for &value in [i8::MIN, -1, 0, i8::MAX].iter() {
+        roundtrip(value);
+    }
+    for &value in [i16::MIN, -1, 0, i16::MAX].iter() {
+        roundtrip(value);
+    }
+    for &value in [i32::MIN, -1, 0, i32::MAX].iter() {
+        roundtrip(value);
+    }
+    for &value in [0u8, u8::MAX].iter() {
+        roundtrip(value);
+    }
+    roundtrip(u16::MAX);
+    roundtrip(u32::MAX);
+    for &value in [f32::MIN, -0.5, 0., f32::MAX, f32::INFINITY].iter() {
+        roundtrip(value);
+    }
+    for &value in [f64::MIN, -0.5, 0., f64::MAX, f64::NEG_INFINITY].iter() {
+        roundtrip(value);
+    }
+    roundtrip('a');
+    roundtrip('\u{10ffff}');
+    roundtrip(String::new());
+    roundtrip("wizard ✨".to_string());
+    roundtrip(());
+}
+
+#[test]
+fn compound_types_roundtrip() {
+    roundtrip(Some(3u8));
+    roundtrip(None::<u8>);
+    roundtrip((1u8, -2i64, 3.5f32));
+    roundtrip(vec![1u64, 200, 40_000]);
+    let map: BTreeMap<u32, String> = vec![(1, "one".to_string()), (300, "three hundred".into())]
+        .into_iter()
+        .collect();
+    roundtrip(map);
+    roundtrip(Message::Unit);
+    roundtrip(Message::Newtype(7));
+    roundtrip(Message::Tuple(-3, true));
+    roundtrip(Message::Struct {
+        name: "fireball".to_string(),
+        position: (12., -4.),
+    });
+    roundtrip(Entity {
+        id: 1 << 40,
+        alive: true,
+        owner: Some(2),
+        tags: vec!["red".to_string()],
+    });
+}
+
+#[test]
+fn bools_take_a_bit() {
+    let data = roundtrip([true, false, true, true, false, false, true, false]);
+    assert_eq!(data.len(), 1);
+    let data = roundtrip((true, 0xabu8));
+    assert_eq!(data.len(), 2);
+}
+
+#[test]
+fn varint_size_grows_with_the_value() {
+    assert_eq!(roundtrip(0u64).len(), 1);
+    assert_eq!(roundtrip(127u64).len(), 1);
+    assert_eq!(roundtrip(128u64).len(), 2);
+    assert_eq!(roundtrip(u64::MAX).len(), 10);
+    // Zigzag keeps small negative values small
+    assert_eq!(roundtrip(-1i64).len(), 1);
+    assert_eq!(roundtrip(-64i64).len(), 1);
+    assert_eq!(roundtrip(-65i64).len(), 2);
+    assert_eq!(roundtrip(i64::MIN).len(), 10);
+    assert_eq!(roundtrip(i64::MAX).len(), 10);
+}
+
+#[test]
+fn bit_writer_and_reader_agree() {
+    let mut writer = BitWriter::new();
+    writer.write_bits(0b101, 3);
+    writer.write_bits(u32::MAX, 32);
+    writer.write_bool(true);
+    writer.write_varint(300);
+    writer.write_bytes(&[1, 2, 3]);
+    assert_eq!(writer.bits_written(), 3 + 32 + 1 + 16 + 24);
+    let data = writer.finish();
+    assert_eq!(data.len(), 10);
+
+    let mut reader = BitReader::new(&data);
+    assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+    assert_eq!(reader.read_bits(32).unwrap(), u32::MAX);
+    assert!(reader.read_bool().unwrap());
+    assert_eq!(reader.read_varint().unwrap(), 300);
+    assert_eq!(reader.read_bytes(3).unwrap(), vec![1, 2, 3]);
+    assert_eq!(reader.remaining_bits(), 4);
+    assert!(matches!(reader.read_bits(8), Err(Error::UnexpectedEnd)));
+}
+
+#[test]
+fn too_long_varint_overflows() {
+    // Eleven groups of 7 bits do not fit in 64 bits
+    let mut writer = BitWriter::new();
+    for _ in 0..11 {
+        writer.write_bits(0x7f, 7);
+        writer.write_bool(true);
+    }
+    let data = writer.finish();
+    assert!(matches!(
+        deserialize::<u64>(&data),
+        Err(Error::VarintOverflow)
+    ));
+
+    // The tenth group only has room for the highest bit
+    let mut writer = BitWriter::new();
+    for _ in 0..9 {
+        writer.write_bits(0x7f, 7);
+        writer.write_bool(true);
+    }
+    writer.write_bits(0b10, 7);
+    writer.write_bool(false);
+    let data = writer.finish();
+    assert!(matches!(
+        deserialize::<u64>(&data),
+        Err(Error::VarintOverflow)
+    ));
+}
+
+#[test]
+fn narrow_integers_reject_wider_values() {
+    let data = serialize(&(u32::MAX as u64 + 1)).unwrap();
+    assert!(matches!(
+        deserialize::<u32>(&data),
+        Err(Error::VarintOverflow)
+    ));
+    let data = serialize(&(i32::MIN as i64 - 1)).unwrap();
+    assert!(matches!(
+        deserialize::<i32>(&data),
+        Err(Error::VarintOverflow)
+    ));
+    let data = serialize(&0xd800u32).unwrap();
+    assert!(matches!(
+        deserialize::<char>(&data),
+        Err(Error::InvalidChar(0xd800))
+    ));
+}
+
+#[test]
+fn truncated_data_is_rejected() {
+    assert!(matches!(deserialize::<u8>(&[]), Err(Error::UnexpectedEnd)));
+    assert!(matches!(
+        deserialize::<bool>(&[]),
+        Err(Error::UnexpectedEnd)
+    ));
+
+    let data = serialize(&Entity {
+        id: u64::MAX,
+        alive: false,
+        owner: None,
+        tags: vec!["blue".to_string()],
+    })
+    .unwrap();
+    for len in 0..data.len() {
+        assert!(
+            matches!(
+                deserialize::<Entity>(&data[..len]),
+                Err(Error::UnexpectedEnd)
+            ),
+            "accepted {} of {} bytes",
+            len,
+            data.len()
+        );
+    }
+
+    // A length longer than the data
+    let mut writer = BitWriter::new();
+    writer.write_varint(1000);
+    writer.write_bytes(b"short");
+    let data = writer.finish();
+    assert!(matches!(
+        deserialize::<String>(&data),
+        Err(Error::UnexpectedEnd)
+    ));
+}
+
+#[test]
+fn trailing_bytes_are_rejected() {
+    let mut data = serialize(&(true, 5u8)).unwrap();
+    assert_eq!(deserialize::<(bool, u8)>(&data).unwrap(), (true, 5));
+    data.push(0);
+    assert!(matches!(
+        deserialize::<(bool, u8)>(&data),
+        Err(Error::TrailingBytes)
+    ));
+}
+
+#[test]
+fn invalid_utf8_is_rejected() {
+    let data = serialize(&vec![0xffu8, 0xfe]).unwrap();
+    assert!(matches!(
+        deserialize::<String>(&data),
+        Err(Error::InvalidUtf8)
+    ));
+}
+
+#[test]
+fn positions_are_quantized_within_the_bounds() {
+    let step = (POSITION_BOUNDS.end() - POSITION_BOUNDS.start()) / u16::MAX as f32;
+    for &(x, y) in [(0., 0.), (335.9, 191.5), (-12.3, 600.7)].iter() {
+        let decoded = position::from_state(position::to_state(&vec2(x, y)));
+        assert!(
+            (decoded.x - x).abs() <= step,
+            "{} decoded as {}",
+            x,
+            decoded.x
+        );
+        assert!(
+            (decoded.y - y).abs() <= step,
+            "{} decoded as {}",
+            y,
+            decoded.y
+        );
+    }
+
+    assert_eq!(position::to_state(&vec2(-1000., 5000.)), (0, u16::MAX));
+    let clamped = position::from_state(position::to_state(&vec2(-1000., 5000.)));
+    assert_eq!(
+        clamped,
+        vec2(*POSITION_BOUNDS.start(), *POSITION_BOUNDS.end())
+    );
+    assert_eq!(
+        position::to_state(&vec2(*POSITION_BOUNDS.start(), *POSITION_BOUNDS.end())),
+        (0, u16::MAX)
+    );
+}
+
+#[test]
+fn angles_wrap_to_a_full_turn() {
+    let step = TAU / 65536.;
+    for &value in [0., 1., PI, TAU - 0.01].iter() {
+        let decoded = angle::from_state(angle::to_state(&value));
+        assert!(
+            (decoded - value).abs() <= step,
+            "{} decoded as {}",
+            value,
+            decoded
+        );
+    }
+    assert_eq!(angle::to_state(&TAU), 0);
+    assert_eq!(
+        angle::to_state(&(-PI / 2.)),
+        angle::to_state(&(3. * PI / 2.))
+    );
+}
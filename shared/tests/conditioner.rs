@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::rc::Rc;
+use std::time::Duration;
+
+use shared::{
+    transport::{
+        ClientTransport, ConditionedTransport, ConditionerHandle, NetworkConditions, TransportError,
+    },
+    Channel,
+};
+
+const SEED: u64 = 0x5eed;
+
+// Messages that went through the conditioner, with their channel.
+type Sent = Rc<RefCell<Vec<(u8, Vec<u8>)>>>;
+
+// Keeps the messages sent through it, the first sends fail when asked to.
+#[derive(Default)]
+struct RecordingTransport {
+    sent: Sent,
+    failures: usize,
+}
+
+impl ClientTransport for RecordingTransport {
+    fn id(&self) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 5000)
+    }
+
+    fn update(&mut self, _duration: Duration) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    fn receive_message(&mut self, _channel_id: u8) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn send_message(&mut self, channel_id: u8, message: Vec<u8>) -> Result<(), TransportError> {
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err(TransportError("send failed".to_string()));
+        }
+        self.sent.borrow_mut().push((channel_id, message));
+        Ok(())
+    }
+
+    fn send_packets(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
+
+fn conditioned(
+    conditions: NetworkConditions,
+    seed: u64,
+) -> (ConditionedTransport<RecordingTransport>, Sent) {
+    let inner = RecordingTransport::default();
+    let sent = inner.sent.clone();
+    let transport =
+        ConditionedTransport::with_seed(inner, ConditionerHandle::new(conditions), seed);
+    (transport, sent)
+}
+
+// Sends one message per millisecond, then waits until every message is delivered.
+fn send_all(
+    transport: &mut ConditionedTransport<RecordingTransport>,
+    channel: Channel,
+    count: u8,
+) -> Result<(), TransportError> {
+    for i in 0..count {
+        transport.send_message(channel.id(), vec![i])?;
+        transport.update(Duration::from_millis(1))?;
+        transport.send_packets()?;
+    }
+    transport.update(Duration::from_secs(1))?;
+    transport.send_packets()
+}
+
+fn sent_values(sent: &Sent) -> Vec<u8> {
+    sent.borrow()
+        .iter()
+        .map(|(_, message)| message[0])
+        .collect()
+}
+
+#[test]
+fn messages_are_delayed_by_the_latency() {
+    let conditions = NetworkConditions {
+        latency: 50.,
+        ..Default::default()
+    };
+    let (mut transport, sent) = conditioned(conditions, SEED);
+    transport
+        .send_message(Channel::Unreliable.id(), vec![1])
+        .unwrap();
+    transport.send_packets().unwrap();
+    assert!(sent.borrow().is_empty());
+
+    transport.update(Duration::from_millis(40)).unwrap();
+    transport.send_packets().unwrap();
+    assert!(sent.borrow().is_empty());
+
+    transport.update(Duration::from_millis(20)).unwrap();
+    transport.send_packets().unwrap();
+    assert_eq!(*sent.borrow(), vec![(Channel::Unreliable.id(), vec![1])]);
+}
+
+#[test]
+fn reliable_messages_keep_their_order() {
+    // Lost reliable messages are resent later, the ones after them wait
+    let conditions = NetworkConditions {
+        latency: 20.,
+        jitter: 30.,
+        loss: 0.5,
+        duplication: 0.5,
+        reordering: 0.5,
+    };
+    let (mut transport, sent) = conditioned(conditions, SEED);
+    send_all(&mut transport, Channel::Reliable, 100).unwrap();
+    assert_eq!(sent_values(&sent), (0..100).collect::<Vec<u8>>());
+}
+
+#[test]
+fn unreliable_messages_are_lost() {
+    let conditions = NetworkConditions {
+        loss: 1.,
+        ..Default::default()
+    };
+    let (mut transport, sent) = conditioned(conditions, SEED);
+    send_all(&mut transport, Channel::Unreliable, 100).unwrap();
+    assert!(sent.borrow().is_empty());
+
+    let conditions = NetworkConditions {
+        loss: 0.5,
+        ..Default::default()
+    };
+    let (mut transport, sent) = conditioned(conditions, SEED);
+    send_all(&mut transport, Channel::Unreliable, 200).unwrap();
+    let delivered = sent_values(&sent);
+    assert!(
+        (50..150).contains(&delivered.len()),
+        "{} of 200 messages delivered",
+        delivered.len()
+    );
+    // The messages left are still in order without reordering
+    assert!(delivered.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn unreliable_messages_are_duplicated() {
+    let conditions = NetworkConditions {
+        duplication: 1.,
+        ..Default::default()
+    };
+    let (mut transport, sent) = conditioned(conditions, SEED);
+    send_all(&mut transport, Channel::Unreliable, 10).unwrap();
+    let expected: Vec<u8> = (0..10).flat_map(|i| vec![i, i]).collect();
+    assert_eq!(sent_values(&sent), expected);
+}
+
+#[test]
+fn same_seed_gives_the_same_conditions() {
+    let conditions = NetworkConditions {
+        latency: 10.,
+        jitter: 20.,
+        loss: 0.3,
+        duplication: 0.3,
+        reordering: 0.3,
+    };
+    let (mut transport, sent) = conditioned(conditions, SEED);
+    send_all(&mut transport, Channel::Unreliable, 200).unwrap();
+    let (mut other_transport, other_sent) = conditioned(conditions, SEED);
+    send_all(&mut other_transport, Channel::Unreliable, 200).unwrap();
+    assert_eq!(sent_values(&sent), sent_values(&other_sent));
+
+    let (mut transport, different_seed_sent) = conditioned(conditions, SEED + 2);
+    send_all(&mut transport, Channel::Unreliable, 200).unwrap();
+    assert_ne!(sent_values(&sent), sent_values(&different_seed_sent));
+}
+
+#[test]
+fn send_error_does_not_drop_the_other_messages() {
+    let inner = RecordingTransport {
+        failures: 1,
+        ..Default::default()
+    };
+    let sent = inner.sent.clone();
+    let conditions = ConditionerHandle::new(NetworkConditions::default());
+    let mut transport = ConditionedTransport::with_seed(inner, conditions, SEED);
+    for i in 0..3 {
+        transport
+            .send_message(Channel::Reliable.id(), vec![i])
+            .unwrap();
+    }
+    assert!(transport.send_packets().is_err());
+    assert_eq!(sent_values(&sent), vec![1, 2]);
+}
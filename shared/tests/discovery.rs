@@ -0,0 +1,42 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use shared::discovery::{DiscoveryResponder, LanBrowser, ServerAnnouncement};
+use shared::message::PROTOCOL_VERSION;
+
+#[test]
+fn lan_browser_finds_the_responder() {
+    let mut responder = DiscoveryResponder::bind(0).unwrap();
+    let port = responder.local_addr().unwrap().port();
+    let query_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut browser = LanBrowser::with_query_addr(query_addr).unwrap();
+
+    let announcement = ServerAnnouncement {
+        protocol_version: PROTOCOL_VERSION,
+        name: "Test server".to_string(),
+        port: 5000,
+        level: "First".to_string(),
+        mode: "deathmatch".to_string(),
+        players: 1,
+        max_players: 8,
+    };
+    for _ in 0..100 {
+        browser.update();
+        responder.update(&announcement);
+        if !browser.servers().is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let servers = browser.servers();
+    assert_eq!(servers.len(), 1);
+    // The game port of the announcement is used with the address of the answer
+    assert_eq!(
+        servers[0].addr,
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 5000))
+    );
+    assert_eq!(servers[0].announcement, announcement);
+    assert!(servers[0].compatible());
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use glam::vec2;
+use shipyard::{EntitiesViewMut, EntityId, Get, Remove, UniqueView, View, ViewMut, World};
+
+use shared::{
+    network::{
+        bits::{angle, deserialize, position, serialize},
+        register_components, NetworkState, ServerFrame,
+    },
+    player::{Player, PlayerNetworkState},
+    projectile::{Projectile, ProjectileNetworkState, ProjectileType},
+    timer::TimerSimple,
+    EntityMapping, Health, PlayerId, Team, Transform,
+};
+
+fn entity() -> EntityId {
+    World::new()
+        .run(|mut entities: EntitiesViewMut| entities.add_entity((), ()))
+        .unwrap()
+}
+
+// The state sent to the clients decodes to the same state.
+fn replicate<T: NetworkState>(component: &T) -> T {
+    let data = serialize(&component.state()).unwrap();
+    let state: T::State = deserialize(&data).unwrap();
+    assert_eq!(state, component.state());
+    T::from_state(state)
+}
+
+#[test]
+fn skipped_fields_are_left_out_of_the_state() {
+    let owner = entity();
+    let mut projectile = Projectile::new(ProjectileType::Fireball, vec2(300., 0.), owner);
+    projectile.rewind_ticks = 6;
+
+    let replicated = replicate(&projectile);
+    assert_eq!(replicated.projectile_type, ProjectileType::Fireball);
+    assert_eq!(replicated.owner, owner);
+    assert_eq!(replicated.duration, Duration::default());
+    assert_eq!(replicated.speed, vec2(0., 0.));
+    assert_eq!(replicated.rewind_ticks, 0);
+
+    // Changes to the skipped fields are not sent
+    let mut moved = projectile.clone();
+    moved.duration = Duration::from_millis(500);
+    moved.speed = vec2(-300., 0.);
+    moved.rewind_ticks = 0;
+    let state: ProjectileNetworkState = moved.state();
+    assert_eq!(state, projectile.state());
+}
+
+#[test]
+fn update_from_state_keeps_the_skipped_fields() {
+    let mut player = Player::new(PlayerId(1));
+    player.fireball_cooldown = TimerSimple::new(3.);
+    player.fireball_max_charge = 2.;
+
+    let mut server_player = Player::new(PlayerId(1));
+    server_player.input_sequence = 42;
+    server_player.team = Some(Team::Blue);
+    server_player.dash_duration = 0.5;
+    let state: PlayerNetworkState = server_player.state();
+    player.update_from_state(state);
+
+    assert_eq!(player.input_sequence, 42);
+    assert_eq!(player.team, Some(Team::Blue));
+    assert_eq!(player.dash_duration, 0.5);
+    assert_eq!(player.fireball_cooldown, TimerSimple::new(3.));
+    assert_eq!(player.fireball_max_charge, 2.);
+
+    let replicated = replicate(&server_player);
+    assert_eq!(replicated.fireball_cooldown, TimerSimple::default());
+    assert_eq!(replicated.fireball_max_charge, 0.);
+}
+
+#[test]
+fn quantized_fields_are_rounded_to_the_step() {
+    let mut player = Player::new(PlayerId(2));
+    player.direction = vec2(0.6, -0.8);
+    player.fireball_charge = 0.4567;
+    player.speed = vec2(123.456, -0.004);
+
+    let replicated = replicate(&player);
+    let direction_error = (replicated.direction - player.direction).abs();
+    assert!(direction_error.max_element() <= 0.0005);
+    assert!((replicated.fireball_charge - 0.46).abs() < 1e-6);
+    assert!((replicated.speed.x - 123.46).abs() < 1e-3);
+    assert_eq!(replicated.speed.y, 0.);
+    // Fields without attributes are sent as they are
+    assert_eq!(replicated.player_id, player.player_id);
+    assert_eq!(replicated.dash_cooldown, player.dash_cooldown);
+}
+
+#[test]
+fn with_fields_use_the_conversion_module() {
+    let transform = Transform::new(vec2(100.3, 57.9), 1.2);
+    let replicated = replicate(&transform);
+    assert_eq!(
+        replicated.position,
+        position::from_state(position::to_state(&transform.position))
+    );
+    assert_eq!(
+        replicated.rotation,
+        angle::from_state(angle::to_state(&transform.rotation))
+    );
+
+    let mut updated = Transform::new(vec2(0., 0.), 0.);
+    updated.update_from_state(transform.state());
+    assert_eq!(updated, replicated);
+}
+
+#[test]
+fn structs_without_attributes_are_their_own_state() {
+    let mut health = Health::new(3);
+    health.current = 1;
+    health.killer = Some(PlayerId(4));
+    let state: Health = health.state();
+    assert_eq!(state, health);
+    assert_eq!(replicate(&health), health);
+}
+
+#[test]
+fn deltas_only_keep_the_changed_fields() {
+    let baseline = Player::new(PlayerId(3));
+    let mut player = baseline.clone();
+    player.fireball_charge = 0.5;
+
+    let delta = Player::delta(&player.state(), Some(&baseline.state()));
+    let full = Player::delta(&player.state(), None);
+    assert!(serialize(&delta).unwrap().len() < serialize(&full).unwrap().len());
+    assert_eq!(
+        Player::apply_delta(Some(&baseline.state()), delta.clone()),
+        Some(player.state())
+    );
+    // The unchanged fields come from the baseline
+    assert_eq!(Player::apply_delta(None, delta), None);
+    assert_eq!(Player::apply_delta(None, full), Some(player.state()));
+
+    let health = Health::new(3);
+    let delta = Health::delta(&health, Some(&health));
+    assert_eq!(Health::apply_delta(Some(&health), delta), Some(health));
+}
+
+#[test]
+fn components_removed_from_live_entities_are_removed_on_the_client() {
+    let registry = register_components();
+    let server = World::new();
+    let server_entity = server
+        .run(
+            |mut entities: EntitiesViewMut,
+             mut health: ViewMut<Health>,
+             mut transforms: ViewMut<Transform>| {
+                entities.add_entity(
+                    (&mut health, &mut transforms),
+                    (Health::new(3), Transform::new(vec2(10., 20.), 0.)),
+                )
+            },
+        )
+        .unwrap();
+    let baseline = ServerFrame::from_world(1, &server, &registry);
+
+    server
+        .run(|mut health: ViewMut<Health>| {
+            Remove::<(Health,)>::remove((&mut health,), server_entity);
+        })
+        .unwrap();
+    let frame = ServerFrame::from_world(2, &server, &registry);
+    let delta = ServerFrame::from_bytes(&frame.delta(&baseline, &registry).to_bytes()).unwrap();
+
+    let client = World::new();
+    let mapping: EntityMapping = HashMap::new();
+    client.add_unique(mapping).unwrap();
+    baseline.apply_in_world(&client, &registry).unwrap();
+    let decoded = delta.decode_delta(&baseline, &registry).unwrap();
+    decoded.apply_in_world(&client, &registry).unwrap();
+
+    client
+        .run(
+            |mapping: UniqueView<EntityMapping>,
+             health: View<Health>,
+             transforms: View<Transform>| {
+                let client_entity = mapping[&server_entity];
+                assert!((&health).get(client_entity).is_err());
+                assert!((&transforms).get(client_entity).is_ok());
+            },
+        )
+        .unwrap();
+}